@@ -139,7 +139,7 @@ impl<'de, I> Deserializer<'de, I>
     }
 }
 
-impl<'a, 'de, I> serde::Deserializer<'de> for &'a mut Deserializer<'de, I>
+impl<'de, I> serde::Deserializer<'de> for &mut Deserializer<'de, I>
     where I: Iterator<Item=ArgIn<'de>>
 {
     type Error = SimpleError;
@@ -276,7 +276,7 @@ impl<'a, 'de, I> serde::Deserializer<'de> for &'a mut Deserializer<'de, I>
         visitor.visit_map(MapAccessImpl { de: self })
     }
 
-    fn deserialize_enum<V>(mut self, name: &'static str, variants: &'static [&'static str], visitor: V)
+    fn deserialize_enum<V>(self, name: &'static str, variants: &'static [&'static str], visitor: V)
                            -> Result<V::Value> where V: Visitor<'de>
     {
         let (field_name, next_enum_value) = self.expect_enum(variants)?;
@@ -284,7 +284,7 @@ impl<'a, 'de, I> serde::Deserializer<'de> for &'a mut Deserializer<'de, I>
         visitor.visit_enum(EnumAccessImpl { de: self, type_name: name, field_name })
     }
 
-    fn deserialize_identifier<V>(mut self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
+    fn deserialize_identifier<V>(self, visitor: V) -> Result<V::Value> where V: Visitor<'de> {
         if let Some(next_enum_value) = self.next_enum_value {
             self.next_enum_value = None;
             return visitor.visit_borrowed_str(next_enum_value);
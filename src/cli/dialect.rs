@@ -1,4 +1,6 @@
 
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use clap::builder::{ValueParser, ValueParserFactory};
 use color_eyre::Help;
 use enum_dispatch::enum_dispatch;
@@ -20,6 +22,23 @@ impl ValueParserFactory for Dialect {
     }
 }
 
+/// Formats the dialect with all of its arguments, in the same format it is parsed from
+impl Display for Dialect {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Dialect::Gnu(gnu) => gnu.fmt(f),
+        }
+    }
+}
+
+impl FromStr for Dialect {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        parse_dialect(s)
+    }
+}
+
 fn parse_dialect(value: &str) -> Result<Dialect> {
     // Parse dialect and arguments
     // Should be in the format {dialect}:{arg1},{arg2},...
@@ -56,3 +75,40 @@ fn parse_dialect_string(value: &str) -> (&str, Vec<(&str, Option<&str>)>) {
 
     (name, arg_map.collect())
 }
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::cli::Dialect;
+    use crate::opts::grammar::Gnu;
+    use crate::opts::grammar::gnu::{LongOptionArgumentFormat, PositionalArgumentsMode};
+
+    #[test]
+    fn test_parse_dialect() -> Result<()> {
+        assert_eq!(super::parse_dialect("gnu")?, Dialect::Gnu(Gnu::default()));
+        assert_eq!(super::parse_dialect("gnu:positional=free,-grouping")?, Dialect::Gnu(Gnu {
+            positional: PositionalArgumentsMode::Free,
+            grouping: false,
+            ..Gnu::default()
+        }));
+        Ok(())
+    }
+
+    #[test]
+    fn test_format_dialect_round_trip() -> Result<()> {
+        let dialect = Dialect::Gnu(Gnu {
+            positional: PositionalArgumentsMode::Forbidden,
+            explicit_positional: false,
+            grouping: false,
+            long_arg: LongOptionArgumentFormat::Space,
+        });
+        let formatted = dialect.to_string();
+        assert_eq!(formatted, "gnu:positional=forbidden,-explicit_positional,-grouping,long_arg=space");
+        assert_eq!(super::parse_dialect(&formatted)?, dialect);
+
+        let dialect = Dialect::Gnu(Gnu::default());
+        assert_eq!(super::parse_dialect(&dialect.to_string())?, dialect);
+        Ok(())
+    }
+}
@@ -1,32 +1,188 @@
 mod dialect;
 mod deserialize_from_args;
 
-use clap::{Args, Parser};
+use std::path::PathBuf;
+use clap::{Args, Parser, Subcommand};
 use clio::{Input, Output};
+use eyre::{bail, Result};
+use crate::opts::parsed_args::OptName;
 
 
 pub use crate::cli::dialect::Dialect;
 
 // Derive arguments with clap
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, subcommand_precedence_over_arg = true)]
 pub struct Cli {
-    /// Input file, use '-' for stdin
-    #[arg(short('i'), long, value_parser, default_value = "-")]
-    pub input: Input,
+    #[clap(flatten)]
+    pub input: InputSpec,
 
     /// Output file, use '-' for stdout
     #[arg(short('o'), long, value_parser, default_value = "-")]
     pub output: Output,
 
+    /// Output the arguments as a shell variable assignment (VAR='...'), instead of as plain arguments
+    #[arg(long, value_name = "VAR", value_parser = parse_env_name)]
+    pub to_env: Option<String>,
+
     #[arg(short('I'), long, default_value = "gnu")]
     pub input_dialect: Dialect,
 
     #[arg(short('O'), long, default_value = "gnu")]
     pub output_dialect: Dialect,
 
+    /// Vocabulary file, listing the options of the processed program with all of their names (e.g. `v|verbose`)
+    #[arg(long, value_name = "FILE")]
+    pub vocabulary: Option<PathBuf>,
+
+    /// Run a script as a program: parse the arguments following the script, apply the script commands to them, and
+    /// then run the target program with the processed arguments (or print them, if there is no target)
+    ///
+    /// The script uses the full script language syntax, and can start with header directives:
+    /// `@target PROGRAM`, `@input-dialect DIALECT`, `@output-dialect DIALECT`, `@vocabulary FILE`
+    /// and `@option NAMES` (e.g. `@option v|verbose`).
+    ///
+    /// Scripts can be made executable, by starting them with `#!/usr/bin/env -S oco --run`.
+    #[arg(long, value_name = "SCRIPT", num_args = 1.., allow_hyphen_values = true,
+          conflicts_with_all = ["input", "from_pid", "from_cmdline_file", "from_env", "commands"])]
+    pub run: Option<Vec<String>>,
+
     #[clap(flatten)]
     pub commands: CommandSpec,
+
+    #[command(subcommand)]
+    pub action: Option<Action>,
+}
+
+impl Cli {
+    /// Checks the combinations of arguments that clap cannot reject by itself
+    pub fn validate(&self) -> Result<()> {
+        // Subcommand names take precedence over commands given as arguments, so these would be split between the two
+        if self.action.is_some() && !self.commands.args.is_empty() {
+            bail!("Commands cannot be given as arguments together with a subcommand, use -c or -f instead");
+        }
+        Ok(())
+    }
+}
+
+/// Actions to perform instead of printing the processed arguments
+#[derive(Subcommand, Debug)]
+pub enum Action {
+    #[command(flatten)]
+    Query(Query),
+
+    /// Generate a shell script that processes its arguments with oco, and then runs the target program with them
+    ///
+    /// The wrapper script is written to the output. Installing it in a directory that comes before the target program
+    /// in PATH, transparently patches every invocation of the target program.
+    Wrap(WrapSpec),
+
+    /// Compare two argument lists, and print how the second one differs from the first one
+    ///
+    /// Options are compared by name (including aliases from the vocabulary), ignoring the relative order of different
+    /// options. The occurrences of an option are compared in order, unless the vocabulary declares it `unordered`
+    /// (e.g. `D|define unordered`). As with diff(1), exits with status 0 if the argument lists are equivalent, 1 if
+    /// they differ and 2 if they cannot be compared.
+    Diff(DiffSpec),
+}
+
+/// Queries for extracting information about the processed arguments from shell scripts.
+///
+/// Option names are given without their prefix, and single-character names are short options (e.g. `o` is `-o` and
+/// `output` is `--output` in the GNU dialect). A long option with a single character is written with `--` (e.g. `--O`).
+///
+/// The queries apply to the arguments processed by the commands, which are given with `-c` or `-f`.
+#[derive(Subcommand, Debug)]
+pub enum Query {
+    /// Print the values of the last occurrence of an option, one per line
+    ///
+    /// Exits with a non-zero status if the option is not present.
+    Get {
+        /// Print the values of all occurrences of the option, instead of just the last one
+        #[arg(short, long)]
+        all: bool,
+
+        #[arg(allow_hyphen_values = true)]
+        name: OptName,
+    },
+
+    /// Exit with a zero status if the option is present, and with a non-zero status otherwise
+    Has {
+        #[arg(allow_hyphen_values = true)]
+        name: OptName,
+    },
+
+    /// Print the number of occurrences of an option
+    Count {
+        #[arg(allow_hyphen_values = true)]
+        name: OptName,
+    },
+}
+
+#[derive(Args, Debug)]
+pub struct WrapSpec {
+    /// The program to run, either as a path or as a name to look up in PATH
+    ///
+    /// The lookup skips the wrapper script itself, so the wrapper never runs itself recursively.
+    #[arg(long, value_name = "PROGRAM")]
+    pub target: String,
+
+    /// Script file with the commands to apply to the arguments
+    #[arg(long, value_name = "FILE")]
+    pub script: PathBuf,
+
+    /// Use full script language syntax for the script
+    #[arg(short('l'), default_value = "false")]
+    pub full_script_syntax: bool,
+
+    /// The oco executable that the wrapper script invokes
+    #[arg(long, value_name = "PATH", default_value = "oco")]
+    pub oco: String,
+}
+
+#[derive(Args, Debug)]
+pub struct DiffSpec {
+    /// File with the first argument list, in the same format as the input (use '-' for stdin)
+    #[arg(value_parser)]
+    pub from: Input,
+
+    /// File with the second argument list
+    #[arg(value_parser)]
+    pub to: Input,
+
+    /// Read the argument lists from NUL-separated command line files (e.g. saved copies of /proc/PID/cmdline)
+    ///
+    /// The program names (argv[0]) are not compared.
+    #[arg(long)]
+    pub cmdline: bool,
+
+    /// Print a script (in the full syntax) that transforms the first argument list into the second one, instead of the
+    /// differences
+    #[arg(long)]
+    pub script: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct InputSpec {
+    /// Input file, use '-' for stdin
+    #[arg(short('i'), long, value_parser, default_value = "-")]
+    pub input: Input,
+
+    /// Read the input arguments from the command line of a running process
+    ///
+    /// The program name (argv[0]) is not parsed as an argument, but it is written to the output before the arguments.
+    #[arg(long, value_name = "PID", conflicts_with_all = ["input", "from_cmdline_file", "from_env"])]
+    pub from_pid: Option<u32>,
+
+    /// Read the input arguments from a NUL-separated command line file (e.g. a saved copy of /proc/PID/cmdline)
+    ///
+    /// The program name (argv[0]) is not parsed as an argument, but it is written to the output before the arguments.
+    #[arg(long, value_name = "PATH", value_parser, conflicts_with_all = ["input", "from_env"])]
+    pub from_cmdline_file: Option<Input>,
+
+    /// Read the input arguments from an environment variable (split according to the input dialect)
+    #[arg(long, value_name = "VAR", value_parser = parse_env_name, conflicts_with = "input")]
+    pub from_env: Option<String>,
 }
 
 #[derive(Args, Debug)]
@@ -45,4 +201,38 @@ pub struct CommandSpec {
 
     #[arg(group="commands", value_name = "COMMAND")]
     pub args: Vec<String>,
-}
\ No newline at end of file
+}
+
+fn parse_env_name(value: &str) -> Result<String> {
+    let mut chars = value.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+            && chars.all(|c| c.is_ascii_alphanumeric() || c == '_');
+    if !valid {
+        bail!("Invalid environment variable name: '{value}'");
+    }
+    Ok(value.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::Parser;
+    use eyre::Result;
+    use crate::cli::{Action, Cli, Query};
+    use crate::util::testing::assert_err_contains;
+
+    #[test]
+    fn test_query_with_commands() -> Result<()> {
+        let cli = Cli::try_parse_from(["oco", "-c", "+foo=bar", "get", "foo"])?;
+        cli.validate()?;
+        assert_eq!(cli.commands.with_commands, "+foo=bar");
+        assert!(matches!(cli.action, Some(Action::Query(Query::Get { all: false, .. }))));
+
+        // The subcommand is not taken as a command, but it cannot be combined with commands given as arguments
+        let cli = Cli::try_parse_from(["oco", "+foo=bar", "get", "foo"])?;
+        assert!(matches!(cli.action, Some(Action::Query(Query::Get { .. }))));
+        assert_err_contains!(cli.validate(), "use -c or -f instead");
+
+        Cli::try_parse_from(["oco", "+foo=bar", "baz"])?.validate()?;
+        Ok(())
+    }
+}
@@ -1,57 +1,1378 @@
+use std::fmt::{Display, Formatter};
+use std::env::VarError;
 use std::io::{BufRead};
-use eyre::Result;
-use crate::opts::parsed_args::{OptName, ParsedArgs, ParsedOpt};
+use eyre::{bail, Context as _, ContextCompat, Result};
+use itertools::Itertools;
+use crate::commands::{Condition, Context, Number, Pattern, Substitution, Transform, ValueSelector};
+use crate::opts::grammar::Grammar;
+use crate::opts::parsed_args::{MergePrecedence, OptMatcher, OptName, ParsedArgs, ParsedOpt};
+use crate::opts::vocabulary::{ValueForm, Vocabulary};
 use crate::util::vec::PushExt;
 
 pub trait CommandParser {
     fn parse_from_script_src<R: BufRead>(&self, script: R) -> Result<Vec<Command>>;
     fn parse_from_script(&self, script: &str) -> Result<Vec<Command>>;
-    fn parse_from_args<'a, I, S>(&self, commands: I) -> Result<Vec<Command>>
+    fn parse_from_args<I, S>(&self, commands: I) -> Result<Vec<Command>>
         where S: AsRef<str>,
               I: Iterator<Item=S>;
 }
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum Command {
+    /// Sets the last occurrence of an option (or adds it), and removes the options that are exclusive with it
     Set { option: OptName, values: Vec<String> },
     Add { option: OptName, values: Vec<String> },
+    /// Adds an option at a position (or at the end, if the position is relative to a missing option)
+    Insert { option: OptName, values: Vec<String>, position: Position },
+    /// Moves all occurrences of an option to a position, keeping their order (unless relative to a missing option)
+    Move { option: OptName, position: Position },
+    /// Sorts the options (stably), without changing the positional arguments
+    Sort { order: SortOrder },
     Remove { option: OptName },
+    /// Removes all options with a name matching the pattern
+    RemoveMatching { pattern: Pattern },
+    /// Adds an option only if it is not present (under any of its names)
+    Default { option: OptName, values: Vec<String> },
+    /// Renames all occurrences of an option in place, optionally replacing their values
+    Rename { option: OptName, new_name: OptName, values: Option<Vec<String>> },
     Append { option: OptName, delimiter: String, items: Vec<String> },
+    Prepend { option: OptName, delimiter: String, items: Vec<String> },
+    /// Removes items from a delimited list in the value of the last occurrence of an option
+    Unappend { option: OptName, delimiter: String, items: ValueSelector, drop_empty: bool },
     RepeatedAdd { option: OptName, values: Vec<String> },
+    /// Limits the last value of the last occurrence of an option to an inclusive range (`max` and `min` only have one
+    /// of the bounds)
+    Clamp { option: OptName, min: Option<Number>, max: Option<Number> },
+    /// Adds an amount to the last value of the last occurrence of an option
+    AddTo { option: OptName, amount: Number },
+    /// Sets an option to the values of the last occurrence of another option, if it is present
+    Copy { from: OptName, to: OptName },
+    /// Sets (or adds, with `add`) an option to a value computed from a template (which can reference options), by
+    /// applying the transforms in order
+    Derive { option: OptName, template: String, transforms: Vec<Transform>, add: bool },
+    /// Sets the entry with a key (an item like `KEY=VALUE`, or just `KEY`) in all the occurrences of an option that
+    /// have it, or adds it.
+    ///
+    /// Without a delimiter, each occurrence of the option holds a single entry (e.g. `-o KEY=VALUE`). With a delimiter,
+    /// the entries are items of a delimited list in the value of the last occurrence (e.g. `-o a,b=c`).
+    KeySet { option: OptName, delimiter: Option<String>, key: String, value: Option<String> },
+    /// Removes the entries with any of the keys (see [Command::KeySet]), and list options that the removal leaves empty
+    KeyRemove { option: OptName, delimiter: Option<String>, keys: Vec<String> },
+    /// Replaces regular expression matches in all the values of an option
+    Replace { option: OptName, substitution: Substitution },
+    ReplacePositional { substitution: Substitution },
+    /// Applies a transform to all the values of an option (e.g. `lower` or `normalize-path`)
+    TransformValues { option: OptName, transform: Transform },
+    TransformPositional { transform: Transform },
+    /// Inserts positional arguments before an index (negative indexes count from the end)
+    InsertPositional { index: isize, values: Vec<String> },
+    AppendPositional { values: Vec<String> },
+    /// Removes the positional argument at an index (negative indexes count from the end), if it exists
+    RemovePositional { index: isize },
+    RemovePositionalValues { values: Vec<String> },
+    RemovePositionalMatching { pattern: Pattern },
+    ClearPositional,
+    MergeEnv { variable: String, precedence: MergePrecedence },
+    /// Removes repeated identical options (with the same name, or an alias, and the same values)
+    Dedupe { option: Option<OptName>, keep: Occurrence },
+    /// Removes repeated items from delimited list values of an option
+    DedupeValues { option: OptName, delimiter: String, keep: Occurrence },
+    /// Splits each occurrence of an option into one occurrence per value, also splitting the values by the delimiter
+    /// (or the delimiter of the option in the vocabulary, if it is delimited)
+    Explode { option: OptName, delimiter: Option<String> },
+    /// Joins the values of all the occurrences of an option into its first occurrence, either as a delimited value
+    /// (with the delimiter, or the one from the vocabulary) or as multiple values (if the option is not delimited)
+    Implode { option: OptName, delimiter: Option<String> },
+    /// Defines a script variable, which can be referenced in values as `${NAME}`
+    Let { name: String, value: String },
+    /// Applies one of the blocks, depending on the condition
+    If { condition: Condition, then: Vec<Command>, otherwise: Vec<Command> },
+    /// Declares a group of mutually exclusive options, in addition to the groups of the vocabulary
+    Exclusive { options: Vec<OptName> },
+    /// Fails with an [AssertionError] if options of an exclusive group are used together
+    CheckExclusive,
+    /// Applies the body for each of the values, with the variable set to the value
+    ForEach { variable: String, source: LoopSource, body: Vec<Command> },
+    /// Applies the body of a macro, with its parameters set to the arguments (which are all expanded before any
+    /// parameter is set). Variables shadowed by the parameters are restored afterwards.
+    Call { params: Vec<(String, String)>, body: Vec<Command> },
+    /// Fails with an [AssertionError] if the condition does not hold
+    Assert { condition: Condition, message: String },
+}
+
+/// The error of a failed assertion, reported to the user as is (without the details of a regular error)
+#[derive(Debug)]
+pub struct AssertionError(pub String);
+
+impl Display for AssertionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for AssertionError {}
+
+/// Which occurrence to keep when removing duplicates
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum Occurrence {
+    #[default]
+    First,
+    Last,
+}
+
+/// The values that a loop iterates over
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum LoopSource {
+    /// All the values of all the occurrences of an option
+    Option(OptName),
+    Positional,
+}
+
+/// Where options are placed among the other options
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Position {
+    First,
+    Last,
+    /// Before the first occurrence of an option
+    Before(OptName),
+    /// After the last occurrence of an option
+    After(OptName),
+}
+
+/// How options are ordered when sorting them
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum SortOrder {
+    /// By option name
+    #[default]
+    Name,
+    /// In the order the options are defined in the vocabulary, followed by unknown options
+    Vocabulary,
 }
 
 impl Command {
-    pub fn apply(self, args: &mut ParsedArgs) {
+    pub fn apply(self, args: &mut ParsedArgs, ctx: &mut Context) -> Result<()> {
         match self {
             Command::Set { option, values } => {
-                args.set_last_option(option, values);
+                let values = ctx.interpolate_all(values, args)?;
+                set_option(args, ctx, option, values);
             }
             Command::Add { option, values } => {
+                let values = ctx.interpolate_all(values, args)?;
                 args.options.push(ParsedOpt { name: option, values })
             }
+            Command::Insert { option, values, position } => {
+                let values = ctx.interpolate_all(values, args)?;
+                let index = resolve_position(&position, &args.options, &ctx.vocabulary).unwrap_or(args.options.len());
+                args.options.insert(index, ParsedOpt { name: option, values });
+            }
+            Command::Move { option, position } => {
+                let matcher = ctx.vocabulary.aliases(&option);
+                let (moved, mut rest): (Vec<_>, Vec<_>) = args.options.iter().cloned()
+                        .partition(|o| matcher.matches(&o.name));
+                if let Some(index) = resolve_position(&position, &rest, &ctx.vocabulary) {
+                    rest.splice(index..index, moved);
+                    args.options = rest;
+                }
+            }
+            Command::Sort { order: SortOrder::Name } => {
+                args.options.sort_by_cached_key(|o| o.name.unprefixed().into_owned());
+            }
+            Command::Sort { order: SortOrder::Vocabulary } => {
+                args.options.sort_by_key(|o| ctx.vocabulary.index_of(&o.name).unwrap_or(usize::MAX));
+            }
             Command::Remove { option } => {
-                args.remove_all_options(option)
+                args.remove_all_options(ctx.vocabulary.aliases(&option))
+            }
+            Command::RemoveMatching { pattern } => {
+                args.remove_all_options(pattern)
+            }
+            Command::Default { option, values } => {
+                let values = ctx.interpolate_all(values, args)?;
+                if args.find_last(ctx.vocabulary.aliases(&option)).is_none() {
+                    args.options.push(ParsedOpt { name: option, values })
+                }
+            }
+            Command::Rename { option, new_name, values } => {
+                let values = values.map(|values| ctx.interpolate_all(values, args)).transpose()?;
+                for renamed in args.find_all_mut(ctx.vocabulary.aliases(&option)) {
+                    renamed.name = new_name.clone();
+                    if let Some(values) = &values {
+                        renamed.values = values.clone();
+                    }
+                }
             }
             Command::RepeatedAdd { option, values: value_for_each } => {
+                let value_for_each = ctx.interpolate_all(value_for_each, args)?;
                 for value in value_for_each {
                     args.options.push(ParsedOpt { name: option.clone(), values: vec![value] })
                 }
             }
+            Command::Clamp { option, min, max } => {
+                update_last_value(args, ctx, &option, |value| Number::clamp(value, min.as_ref(), max.as_ref()))?;
+            }
+            Command::AddTo { option, amount } => {
+                update_last_value(args, ctx, &option, |value| amount.add_to(value))?;
+            }
+            Command::Copy { from, to } => {
+                if let Some(source) = args.find_last(ctx.vocabulary.aliases(&from)) {
+                    let values = source.values.clone();
+                    set_option(args, ctx, to, values);
+                }
+            }
+            Command::Derive { option, template, transforms, add } => {
+                let value = transforms.into_iter()
+                        .try_fold(ctx.interpolate(&template, args)?, |value, transform| {
+                            interpolate_transform(transform, args, ctx)?.apply(&value)
+                        })?;
+                if add {
+                    args.options.push(ParsedOpt { name: option, values: vec![value] });
+                } else {
+                    set_option(args, ctx, option, vec![value]);
+                }
+            }
+            Command::KeySet { option, delimiter, key, value } => {
+                let key = ctx.interpolate(&key, args)?;
+                let entry = match value {
+                    Some(value) => format!("{key}={}", ctx.interpolate(&value, args)?),
+                    None => key.clone(),
+                };
+                let matcher = ctx.vocabulary.aliases(&option);
+                match delimiter {
+                    None => {
+                        let mut found = false;
+                        let keyed = args.find_all_mut(matcher)
+                                .filter(|o| o.values.first().is_some_and(|entry| has_key(entry, &key)));
+                        for keyed in keyed {
+                            keyed.values = vec![entry.clone()];
+                            found = true;
+                        }
+                        if !found {
+                            args.options.push(ParsedOpt { name: option, values: vec![entry] });
+                        }
+                    }
+                    Some(delimiter) if delimiter.is_empty() => {
+                        bail!("The delimiter for the entries of {option} cannot be empty");
+                    }
+                    Some(delimiter) => {
+                        args.update_last_option(matcher, option.clone(), |values| {
+                            let first_value = values.ensure_first_or_default();
+                            // Other items are kept as they are, including empty ones (unless the list is empty)
+                            let mut items = if first_value.is_empty() {
+                                vec![]
+                            } else {
+                                first_value.split(delimiter.as_str()).map(str::to_string).collect_vec()
+                            };
+                            let mut found = false;
+                            for item in items.iter_mut().filter(|item| has_key(item, &key)) {
+                                item.clone_from(&entry);
+                                found = true;
+                            }
+                            if !found {
+                                items.push(entry);
+                            }
+                            *first_value = items.join(delimiter.as_str());
+                        });
+                    }
+                }
+            }
+            Command::KeyRemove { option, delimiter, keys } => {
+                let keys = ctx.interpolate_all(keys, args)?;
+                let matcher = ctx.vocabulary.aliases(&option);
+                let is_removed = |entry: &str| keys.iter().any(|key| has_key(entry, key));
+                match delimiter {
+                    None => {
+                        args.options.retain(|o| {
+                            !matcher.matches(&o.name) || !o.values.first().is_some_and(|entry| is_removed(entry))
+                        });
+                    }
+                    Some(delimiter) if delimiter.is_empty() => {
+                        bail!("The delimiter for the entries of {option} cannot be empty");
+                    }
+                    Some(delimiter) => {
+                        if let Some(index) = args.options.iter().rposition(|o| matcher.matches(&o.name)) {
+                            let values = &mut args.options[index].values;
+                            if let Some(first_value) = values.first_mut() {
+                                let (removed, kept): (Vec<_>, Vec<_>) = first_value.split(delimiter.as_str())
+                                        .partition(|item| is_removed(item));
+                                if !removed.is_empty() {
+                                    *first_value = kept.join(delimiter.as_str());
+                                    if first_value.is_empty() {
+                                        args.options.remove(index);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
+            }
             Command::Append { option, delimiter, items  } => {
-                args.update_last_option(option, |values| {
+                let items = ctx.interpolate_all(items, args)?;
+                args.update_last_option(ctx.vocabulary.aliases(&option), option.clone(), |values| {
                     let first_value = values.ensure_first_or_default();
-
-                    if delimiter.is_empty() {
-                        first_value.extend(items);
-                    } else {
-                        for item in items {
-                            first_value.push_str(delimiter.as_str());
-                            first_value.push_str(&item);
-                        }
+                    // Like prepend, the delimiter only separates the items from an existing (non-empty) value
+                    if !first_value.is_empty() && !items.is_empty() {
+                        first_value.push_str(delimiter.as_str());
+                    }
+                    first_value.push_str(&items.join(delimiter.as_str()));
+                });
+            }
+            Command::Prepend { option, delimiter, items } => {
+                let items = ctx.interpolate_all(items, args)?;
+                args.update_last_option(ctx.vocabulary.aliases(&option), option.clone(), |values| {
+                    let first_value = values.ensure_first_or_default();
+                    let mut prefix = items.join(delimiter.as_str());
+                    if !prefix.is_empty() && !first_value.is_empty() {
+                        prefix.push_str(delimiter.as_str());
+                    }
+                    first_value.insert_str(0, &prefix);
+                });
+            }
+            Command::Unappend { option, delimiter, items, drop_empty } => {
+                if delimiter.is_empty() {
+                    bail!("The delimiter for removing items from {option} cannot be empty");
+                }
+                let items = match items {
+                    ValueSelector::Exact(values) => ValueSelector::Exact(ctx.interpolate_all(values, args)?),
+                    matching => matching,
+                };
+                let matcher = ctx.vocabulary.aliases(&option);
+                if let Some(index) = args.options.iter().rposition(|o| matcher.matches(&o.name)) {
+                    let values = &mut args.options[index].values;
+                    if let Some(first_value) = values.first_mut() {
+                        *first_value = first_value.split(delimiter.as_str())
+                                .filter(|item| !items.matches(item))
+                                .join(delimiter.as_str());
+                    }
+                    if drop_empty && values.iter().all(String::is_empty) {
+                        args.options.remove(index);
+                    }
+                }
+            }
+            Command::If { condition, then, otherwise } => {
+                let block = if condition.evaluate(args, ctx)? { then } else { otherwise };
+                for command in block {
+                    command.apply(args, ctx)?;
+                }
+            }
+            Command::ForEach { variable, source, body } => {
+                // The values are taken before the loop, so the body cannot change what is iterated over
+                let values = match source {
+                    LoopSource::Option(option) => {
+                        args.find_all(ctx.vocabulary.aliases(&option)).flat_map(|o| o.values.clone()).collect_vec()
+                    }
+                    LoopSource::Positional => args.positional.clone(),
+                };
+                let shadowed = ctx.variables.remove(&variable);
+                for value in values {
+                    ctx.variables.insert(variable.clone(), value);
+                    for command in body.iter().cloned() {
+                        command.apply(args, ctx)?;
+                    }
+                }
+                match shadowed {
+                    Some(shadowed) => ctx.variables.insert(variable, shadowed),
+                    None => ctx.variables.remove(&variable),
+                };
+            }
+            Command::Call { params, body } => {
+                let values: Vec<String> = params.iter().map(|(_, value)| ctx.interpolate(value, args)).try_collect()?;
+                let shadowed = params.iter().map(|(name, _)| ctx.variables.get(name).cloned()).collect_vec();
+                for ((name, _), value) in params.iter().zip(values) {
+                    ctx.variables.insert(name.clone(), value);
+                }
+                let result = body.into_iter().try_for_each(|command| command.apply(args, ctx));
+                // Restored in reverse, so a repeated parameter gets back the value it had before the call
+                for ((name, _), shadowed) in params.into_iter().zip(shadowed).rev() {
+                    match shadowed {
+                        Some(shadowed) => ctx.variables.insert(name, shadowed),
+                        None => ctx.variables.remove(&name),
+                    };
+                }
+                result?;
+            }
+            Command::Exclusive { options } => {
+                ctx.vocabulary.add_exclusive_names(options)?;
+            }
+            Command::CheckExclusive => {
+                for group in ctx.vocabulary.exclusive_groups() {
+                    let present = group.iter()
+                            .filter_map(|member| args.find_all(ctx.vocabulary.aliases(member)).next())
+                            .map(|found| found.name.to_string())
+                            .collect_vec();
+                    if present.len() > 1 {
+                        let message = format!("Options cannot be used together: {}", present.join(", "));
+                        return Err(AssertionError(message).into());
+                    }
+                }
+            }
+            Command::Assert { condition, message } => {
+                if !condition.evaluate(args, ctx)? {
+                    return Err(AssertionError(ctx.interpolate(&message, args)?).into());
+                }
+            }
+            Command::Replace { option, substitution } => {
+                let options = args.find_all_mut(ctx.vocabulary.aliases(&option)).rev().take(substitution.limit());
+                for value in options.flat_map(|option| option.values.iter_mut()) {
+                    substitution.apply_in_place(value);
+                }
+            }
+            Command::ReplacePositional { substitution } => {
+                for value in args.positional.iter_mut().rev().take(substitution.limit()) {
+                    substitution.apply_in_place(value);
+                }
+            }
+            Command::TransformValues { option, transform } => {
+                let transform = interpolate_transform(transform, args, ctx)?;
+                for found in args.find_all_mut(ctx.vocabulary.aliases(&option)) {
+                    for value in found.values.iter_mut() {
+                        *value = transform.apply(value)?;
+                    }
+                }
+            }
+            Command::TransformPositional { transform } => {
+                let transform = interpolate_transform(transform, args, ctx)?;
+                for value in args.positional.iter_mut() {
+                    *value = transform.apply(value)?;
+                }
+            }
+            Command::InsertPositional { index, values } => {
+                let values = ctx.interpolate_all(values, args)?;
+                let len = args.positional.len();
+                let index = resolve_index(index, len).unwrap_or(if index < 0 { 0 } else { len });
+                args.positional.splice(index..index, values);
+            }
+            Command::AppendPositional { values } => {
+                let values = ctx.interpolate_all(values, args)?;
+                args.positional.extend(values)
+            }
+            Command::RemovePositional { index } => {
+                let len = args.positional.len();
+                if let Some(index) = resolve_index(index, len).filter(|&index| index < len) {
+                    args.positional.remove(index);
+                }
+            }
+            Command::RemovePositionalValues { values } => {
+                let values = ctx.interpolate_all(values, args)?;
+                args.positional.retain(|value| !values.contains(value))
+            }
+            Command::RemovePositionalMatching { pattern } => {
+                args.positional.retain(|value| !pattern.is_match(value))
+            }
+            Command::ClearPositional => {
+                args.positional.clear()
+            }
+            Command::Dedupe { option, keep } => {
+                let selector = option.as_ref().map(|option| ctx.vocabulary.aliases(option));
+                let options = std::mem::take(&mut args.options);
+                args.options = dedupe(options, keep, |a, b| {
+                    selector.is_none_or(|selector| selector.matches(&a.name))
+                            && ctx.vocabulary.aliases(&a.name).matches(&b.name)
+                            && a.values == b.values
+                });
+            }
+            Command::DedupeValues { option, delimiter, keep } => {
+                if delimiter.is_empty() {
+                    bail!("The delimiter for removing duplicate items from {option} cannot be empty");
+                }
+                for found in args.find_all_mut(ctx.vocabulary.aliases(&option)) {
+                    for value in found.values.iter_mut() {
+                        *value = dedupe(value.split(delimiter.as_str()).collect(), keep, |a, b| a == b)
+                                .join(delimiter.as_str());
                     }
+                }
+            }
+            Command::Explode { option, delimiter } => {
+                let delimiter = delimiter.or_else(|| match ctx.vocabulary.value_form(&option) {
+                    Some(ValueForm::Delimited(delimiter)) => Some(delimiter.clone()),
+                    _ => None,
                 });
+                if delimiter.as_deref() == Some("") {
+                    bail!("The delimiter for splitting the values of {option} cannot be empty");
+                }
+                let matcher = ctx.vocabulary.aliases(&option);
+                args.options = args.options.drain(..).flat_map(|found| {
+                    if !matcher.matches(&found.name) || found.values.is_empty() {
+                        return vec![found];
+                    }
+                    let values = match &delimiter {
+                        Some(delimiter) => {
+                            found.values.iter().flat_map(|value| value.split(delimiter.as_str())).map_into().collect()
+                        }
+                        None => found.values,
+                    };
+                    values.into_iter()
+                            .map(|value| ParsedOpt { name: found.name.clone(), values: vec![value] })
+                            .collect()
+                }).collect();
+            }
+            Command::Implode { option, delimiter } => {
+                let form = match delimiter {
+                    Some(delimiter) => ValueForm::Delimited(delimiter),
+                    None => ctx.vocabulary.value_form(&option).cloned().unwrap_or(ValueForm::Variadic),
+                };
+                let delimiter = match form {
+                    ValueForm::Delimited(delimiter) if delimiter.is_empty() => {
+                        bail!("The delimiter for joining the values of {option} cannot be empty");
+                    }
+                    ValueForm::Delimited(delimiter) => Some(delimiter),
+                    ValueForm::Variadic => None,
+                    ValueForm::Repeated => bail!("Option {option} takes repeated values, so joining needs a delimiter"),
+                };
+                let matcher = ctx.vocabulary.aliases(&option);
+                let Some(first) = args.options.iter().position(|o| matcher.matches(&o.name)) else {
+                    return Ok(());
+                };
+                let name = args.options[first].name.clone();
+                let values = args.find_all(matcher).flat_map(|o| o.values.iter().cloned()).collect_vec();
+                let values = match delimiter {
+                    Some(delimiter) if !values.is_empty() => vec![values.join(delimiter.as_str())],
+                    _ => values,
+                };
+                // The first occurrence is not preceded by other occurrences, so its index is kept after removing them
+                args.remove_all_options(matcher);
+                args.options.insert(first, ParsedOpt { name, values });
             }
+            Command::Let { name, value } => {
+                let value = ctx.interpolate(&value, args)?;
+                ctx.variables.insert(name, value);
+            }
+            Command::MergeEnv { variable, precedence } => {
+                let value = match (ctx.env)(&variable) {
+                    Ok(value) => value,
+                    Err(VarError::NotPresent) => return Ok(()), // Nothing to merge
+                    Err(VarError::NotUnicode(_)) => bail!("Environment variable {variable} is not valid unicode"),
+                };
+                let env_args = ctx.dialect.split(&value)
+                        .and_then(|split| ctx.dialect.parse(split.into_iter()))
+                        .wrap_err_with(|| format!("Cannot parse arguments from environment variable {variable}"))?;
+                args.merge(env_args, precedence, &ctx.vocabulary);
+            }
+        }
+        Ok(())
+    }
+}
+
+
+/// Removes items that are duplicates of other items, keeping either their first or last occurrence
+fn dedupe<T, F: Fn(&T, &T) -> bool>(mut items: Vec<T>, keep: Occurrence, is_duplicate: F) -> Vec<T> {
+    if keep == Occurrence::Last {
+        items.reverse();
+    }
+    let mut kept: Vec<T> = Vec::with_capacity(items.len());
+    for item in items {
+        if !kept.iter().any(|existing| is_duplicate(existing, &item)) {
+            kept.push(item);
+        }
+    }
+    if keep == Occurrence::Last {
+        kept.reverse();
+    }
+    kept
+}
+
+/// Sets the last occurrence of an option (or adds it), and removes the options that are exclusive with it
+fn set_option(args: &mut ParsedArgs, ctx: &Context, option: OptName, values: Vec<String>) {
+    for exclusive in ctx.vocabulary.exclusive_with(&option) {
+        args.remove_all_options(ctx.vocabulary.aliases(exclusive));
+    }
+    args.set_last_option(ctx.vocabulary.aliases(&option), option.clone(), values);
+}
+
+/// Replaces the last value of the last occurrence of an option (if it is present) with a value computed from it
+fn update_last_value<F>(args: &mut ParsedArgs, ctx: &Context, option: &OptName, update: F) -> Result<()>
+    where F: FnOnce(&str) -> Result<String>
+{
+    if let Some(found) = args.find_all_mut(ctx.vocabulary.aliases(option)).next_back() {
+        let value = found.values.last_mut().wrap_err_with(|| format!("Option {option} has no value"))?;
+        *value = update(value).wrap_err_with(|| format!("Cannot update the value of {option}"))?;
+    }
+    Ok(())
+}
+
+/// Checks whether an entry (`KEY=VALUE`, or just `KEY`) has the given key
+fn has_key(entry: &str, key: &str) -> bool {
+    entry.split_once('=').map_or(entry, |(entry_key, _)| entry_key) == key
+}
+
+/// Expands the references in the directory of a path transform
+fn interpolate_transform(transform: Transform, args: &ParsedArgs, ctx: &Context) -> Result<Transform> {
+    match transform {
+        Transform::NormalizePath { relative_to: Some(dir) } => {
+            Ok(Transform::NormalizePath { relative_to: Some(ctx.interpolate(&dir, args)?) })
         }
+        transform => Ok(transform),
     }
 }
 
+/// Resolves the index that options are inserted at for a position, if the option it is relative to is present
+fn resolve_position(position: &Position, options: &[ParsedOpt], vocabulary: &Vocabulary) -> Option<usize> {
+    match position {
+        Position::First => Some(0),
+        Position::Last => Some(options.len()),
+        Position::Before(other) => {
+            let matcher = vocabulary.aliases(other);
+            options.iter().position(|o| matcher.matches(&o.name))
+        }
+        Position::After(other) => {
+            let matcher = vocabulary.aliases(other);
+            options.iter().rposition(|o| matcher.matches(&o.name)).map(|index| index + 1)
+        }
+    }
+}
+
+/// Resolves a possibly negative index (counting from the end) into an index in `0..=len`
+fn resolve_index(index: isize, len: usize) -> Option<usize> {
+    let resolved = if index < 0 { len.checked_sub(index.unsigned_abs())? } else { index as usize };
+    (resolved <= len).then_some(resolved)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::VarError;
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::cli::Dialect;
+    use crate::commands::{
+        AssertionError, Command, Condition, Context, LoopSource, Number, Occurrence, Pattern, Position, SortOrder,
+        Substitution, Transform, ValueSelector,
+    };
+    use crate::opts::grammar::Gnu;
+    use crate::opts::parsed_args::{MergePrecedence, ParsedArgs};
+    use crate::opts::vocabulary::Vocabulary;
+    use crate::util::testing::assert_err_contains;
+    use crate::util::testing::opts::sv;
+    use crate::util::testing::opts::parsed::{long, short};
+    use crate::util::testing::opts::name;
+
+    fn apply_all(args: &mut ParsedArgs, commands: Vec<Command>) -> Result<()> {
+        apply_all_with_vocabulary(args, commands, Vocabulary::default())
+    }
+
+    fn apply_all_with_vocabulary(args: &mut ParsedArgs, commands: Vec<Command>, vocabulary: Vocabulary) -> Result<()> {
+        apply_all_with_context(args, commands, &mut Context::new(Dialect::Gnu(Gnu::default()), vocabulary))
+    }
+
+    fn apply_all_with_context(args: &mut ParsedArgs, commands: Vec<Command>, context: &mut Context) -> Result<()> {
+        for command in commands {
+            command.apply(args, context)?;
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_env() -> Result<()> {
+        let mut ctx = Context::new(Dialect::Gnu(Gnu::default()), Vocabulary::default());
+        ctx.env = |name| match name {
+            "OCO_TEST_MERGE_ENV" => Ok("--color=always -i -- 'file 2'".to_string()),
+            _ => Err(VarError::NotPresent),
+        };
+
+        let mut args = ParsedArgs { options: vec![long!("color", "never")], positional: sv!["file1"] };
+        apply_all_with_context(&mut args, vec![
+            Command::MergeEnv { variable: "OCO_TEST_MERGE_ENV".to_string(), precedence: MergePrecedence::Existing },
+            // Unset variables are ignored
+            Command::MergeEnv { variable: "OCO_TEST_MERGE_ENV_UNSET".to_string(), precedence: MergePrecedence::Merged },
+        ], &mut ctx)?;
+        assert_eq!(args, ParsedArgs {
+            options: vec![short!('i'), long!("color", "never")],
+            positional: sv!["file 2", "file1"],
+        });
+
+        let mut args = ParsedArgs { options: vec![long!("color", "never")], positional: sv!["file1"] };
+        apply_all_with_context(&mut args, vec![
+            Command::MergeEnv { variable: "OCO_TEST_MERGE_ENV".to_string(), precedence: MergePrecedence::Merged },
+        ], &mut ctx)?;
+        assert_eq!(args, ParsedArgs {
+            options: vec![long!("color", "always"), short!('i')],
+            positional: sv!["file1", "file 2"],
+        });
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_without_vocabulary() -> Result<()> {
+        // Without a vocabulary, commands only affect options with exactly the same name
+        let commands = || vec![
+            Command::Set { option: name::long!("color"), values: sv!["always"] },
+            Command::Append { option: name::short!('c'), delimiter: ",".to_string(), items: sv!["2"] },
+            Command::Remove { option: name::long!("c") },
+        ];
+        let options = vec![short!('c', "1"), long!("color", "never"), long!("c", "x"), long!("color", "auto")];
+
+        let mut args = ParsedArgs { options: options.clone(), positional: sv![] };
+        apply_all(&mut args, commands())?;
+        assert_eq!(args, ParsedArgs {
+            options: vec![short!('c', "1,2"), long!("color", "never"), long!("color", "always")],
+            positional: sv![],
+        });
+
+        // Aliases are only matched once they are declared
+        let mut args = ParsedArgs { options, positional: sv![] };
+        apply_all_with_vocabulary(&mut args, commands(), Vocabulary::parse("c|color")?)?;
+        assert_eq!(args, ParsedArgs {
+            options: vec![short!('c', "1"), long!("color", "never"), long!("color", "always,2")],
+            positional: sv![],
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_rename() -> Result<()> {
+        let mut args = ParsedArgs {
+            options: vec![short!('v'), long!("color", "auto"), long!("verbose"), short!('x', "1")],
+            positional: sv!["file"],
+        };
+        apply_all_with_vocabulary(&mut args, vec![
+            Command::Rename {
+                option: name::long!("verbose"),
+                new_name: name::long!("log-level"),
+                values: Some(sv!["debug"]),
+            },
+            Command::Rename { option: name::short!('x'), new_name: name::long!("extra"), values: None },
+            // Missing options are ignored
+            Command::Rename { option: name::long!("missing"), new_name: name::long!("other"), values: None },
+        ], Vocabulary::parse("v|verbose")?)?;
+        assert_eq!(args, ParsedArgs {
+            options: vec![
+                long!("log-level", "debug"),
+                long!("color", "auto"),
+                long!("log-level", "debug"),
+                long!("extra", "1"),
+            ],
+            positional: sv!["file"],
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_default() -> Result<()> {
+        let mut args = ParsedArgs { options: vec![long!("colour", "never")], positional: sv!["file"] };
+        apply_all_with_vocabulary(&mut args, vec![
+            Command::Default { option: name::long!("color"), values: sv!["auto"] },
+            Command::Default { option: name::short!('s'), values: sv![] },
+            // Already added by the previous command
+            Command::Default { option: name::short!('s'), values: sv!["1"] },
+        ], Vocabulary::parse("color|colour")?)?;
+        assert_eq!(args, ParsedArgs {
+            options: vec![long!("colour", "never"), short!('s')],
+            positional: sv!["file"],
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_if() -> Result<()> {
+        let if_ci = |then: Vec<Command>, otherwise: Vec<Command>| Command::If {
+            condition: Condition::Has(name::long!("ci")),
+            then,
+            otherwise,
+        };
+
+        let mut args = ParsedArgs { options: vec![long!("ci")], positional: sv![] };
+        apply_all(&mut args, vec![
+            if_ci(vec![Command::Add { option: name::long!("no-cache"), values: sv![] }], vec![]),
+            if_ci(vec![], vec![Command::Add { option: name::long!("cache"), values: sv![] }]),
+        ])?;
+        assert_eq!(args, ParsedArgs { options: vec![long!("ci"), long!("no-cache")], positional: sv![] });
+
+        let mut args = ParsedArgs::default();
+        apply_all(&mut args, vec![
+            if_ci(vec![], vec![Command::Add { option: name::long!("cache"), values: sv![] }]),
+        ])?;
+        assert_eq!(args, ParsedArgs { options: vec![long!("cache")], positional: sv![] });
+        Ok(())
+    }
+
+    #[test]
+    fn test_insert() -> Result<()> {
+        let mut args = ParsedArgs {
+            options: vec![short!('L', "a"), short!('x', "c"), short!('L', "b")],
+            positional: sv![],
+        };
+        let insert = |option, position| Command::Insert { option, values: sv!["new"], position };
+        apply_all_with_vocabulary(&mut args, vec![
+            insert(name::short!('L'), Position::First),
+            insert(name::short!('I'), Position::Before(name::long!("lang"))),
+            insert(name::short!('O'), Position::After(name::short!('L'))),
+            insert(name::short!('v'), Position::After(name::long!("missing"))),
+        ], Vocabulary::parse("x|lang")?)?;
+        assert_eq!(args.options, vec![
+            short!('L', "new"), short!('L', "a"), short!('I', "new"), short!('x', "c"), short!('L', "b"),
+            short!('O', "new"), short!('v', "new"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_move() -> Result<()> {
+        let original = vec![short!('L', "a"), short!('x', "c"), long!("lib", "b"), short!('v')];
+        let move_option = |option, position| -> Result<_> {
+            let mut args = ParsedArgs { options: original.clone(), positional: sv!["file"] };
+            let vocabulary = Vocabulary::parse("L|lib")?;
+            apply_all_with_vocabulary(&mut args, vec![Command::Move { option, position }], vocabulary)?;
+            Ok(args.options)
+        };
+
+        assert_eq!(move_option(name::short!('v'), Position::First)?,
+                   vec![short!('v'), short!('L', "a"), short!('x', "c"), long!("lib", "b")]);
+        assert_eq!(move_option(name::short!('L'), Position::Last)?,
+                   vec![short!('x', "c"), short!('v'), short!('L', "a"), long!("lib", "b")]);
+        assert_eq!(move_option(name::short!('x'), Position::After(name::short!('L')))?,
+                   vec![short!('L', "a"), long!("lib", "b"), short!('x', "c"), short!('v')]);
+        assert_eq!(move_option(name::short!('v'), Position::Before(name::short!('x')))?,
+                   vec![short!('L', "a"), short!('v'), short!('x', "c"), long!("lib", "b")]);
+        assert_eq!(move_option(name::short!('v'), Position::Before(name::short!('o')))?, original);
+        Ok(())
+    }
+
+    #[test]
+    fn test_sort() -> Result<()> {
+        let original = vec![long!("verbose"), short!('c', "2"), long!("all"), short!('c', "1"), short!('v')];
+        let mut args = ParsedArgs { options: original.clone(), positional: sv!["b", "a"] };
+        apply_all(&mut args, vec![Command::Sort { order: SortOrder::Name }])?;
+        assert_eq!(args, ParsedArgs {
+            options: vec![long!("all"), short!('c', "2"), short!('c', "1"), short!('v'), long!("verbose")],
+            positional: sv!["b", "a"],
+        });
+
+        let mut args = ParsedArgs { options: original, positional: sv![] };
+        let vocabulary = Vocabulary::parse("v|verbose\nc|count")?;
+        apply_all_with_vocabulary(&mut args, vec![Command::Sort { order: SortOrder::Vocabulary }], vocabulary)?;
+        assert_eq!(args.options, vec![long!("verbose"), short!('v'), short!('c', "2"), short!('c', "1"), long!("all")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_values() -> Result<()> {
+        let mut args = ParsedArgs {
+            options: vec![short!('o', "Port=22"), short!('e', "DEBUG"), short!('o', "Port=2"), short!('o', "User=a")],
+            positional: sv![],
+        };
+        let set = |option, key: &str, value: Option<&str>| Command::KeySet {
+            option,
+            delimiter: None,
+            key: key.to_string(),
+            value: value.map(str::to_string),
+        };
+        apply_all(&mut args, vec![
+            set(name::short!('o'), "Port", Some("${OCO_TEST_UNSET_PORT:-80}")),
+            set(name::short!('o'), "ServerAliveInterval", Some("30")),
+            set(name::short!('e'), "TRACE", None),
+            Command::KeyRemove { option: name::short!('e'), delimiter: None, keys: sv!["DEBUG"] },
+            Command::KeyRemove { option: name::short!('o'), delimiter: None, keys: sv!["User", "Other"] },
+        ])?;
+        assert_eq!(args.options, vec![
+            short!('o', "Port=80"), short!('o', "Port=80"), short!('o', "ServerAliveInterval=30"), short!('e', "TRACE"),
+        ]);
+
+        let mut args = ParsedArgs {
+            options: vec![short!('o', "ro"), short!('o', "rw,uid=1,noatime")],
+            positional: sv![],
+        };
+        let set = |option, key: &str, value: Option<&str>| Command::KeySet {
+            option,
+            delimiter: Some(",".to_string()),
+            key: key.to_string(),
+            value: value.map(str::to_string),
+        };
+        let remove = |keys| Command::KeyRemove { option: name::short!('o'), delimiter: Some(",".to_string()), keys };
+        apply_all(&mut args, vec![
+            set(name::short!('o'), "uid", Some("0")),
+            set(name::short!('o'), "gid", Some("2")),
+            remove(sv!["noatime"]),
+            set(name::long!("opts"), "ro", None),
+        ])?;
+        assert_eq!(args.options, vec![short!('o', "ro"), short!('o', "rw,uid=0,gid=2"), long!("opts", "ro")]);
+
+        apply_all(&mut args, vec![remove(sv!["rw", "uid", "gid"])])?;
+        assert_eq!(args.options, vec![short!('o', "ro"), long!("opts", "ro")]);
+
+        // Untouched items are kept verbatim, and only a list emptied by the removal removes the option
+        let mut args = ParsedArgs { options: vec![short!('o', "a,,b=1,")], positional: sv![] };
+        apply_all(&mut args, vec![
+            set(name::short!('o'), "${OCO_TEST_UNSET_KEY:-b}", Some("2")),
+            remove(sv!["${OCO_TEST_UNSET_KEY:-a}"]),
+        ])?;
+        assert_eq!(args.options, vec![short!('o', ",b=2,")]);
+
+        for options in [vec![short!('o')], vec![short!('o', "")], vec![short!('o', "x")]] {
+            let mut args = ParsedArgs { options: options.clone(), positional: sv![] };
+            apply_all(&mut args, vec![remove(sv!["a"])])?;
+            assert_eq!(args.options, options);
+        }
+        let mut args = ParsedArgs { options: vec![short!('o', "a"), short!('o', "a")], positional: sv![] };
+        apply_all(&mut args, vec![remove(sv!["a"])])?;
+        assert_eq!(args.options, vec![short!('o', "a")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_copy_and_derive() -> Result<()> {
+        let mut args = ParsedArgs {
+            options: vec![long!("output", "build/Main.o"), long!("target", "x86"), long!("cache-dir", "/tmp")],
+            positional: sv![],
+        };
+        apply_all(&mut args, vec![
+            Command::Copy { from: name::long!("target"), to: name::long!("arch") },
+            Command::Copy { from: name::long!("missing"), to: name::long!("arch") },
+            Command::Derive {
+                option: name::long!("cache-dir"),
+                template: "${opt:output}".to_string(),
+                transforms: vec![Transform::Dirname],
+                add: false,
+            },
+            Command::Derive {
+                option: name::short!('C'),
+                template: "name=${opt:output}".to_string(),
+                transforms: vec![Transform::Basename, Transform::StripSuffix(".o".to_string()), Transform::Lower],
+                add: true,
+            },
+        ])?;
+        assert_eq!(args.options, vec![
+            long!("output", "build/Main.o"), long!("target", "x86"), long!("cache-dir", "build"), long!("arch", "x86"),
+            short!('C', "main"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_for_each() -> Result<()> {
+        let mut args = ParsedArgs {
+            options: vec![short!('I', "a"), long!("include", "b", "c"), short!('v')],
+            positional: sv!["x.c", "y.c"],
+        };
+        apply_all_with_vocabulary(&mut args, vec![
+            Command::Let { name: "f".to_string(), value: "outer".to_string() },
+            Command::ForEach {
+                variable: "dir".to_string(),
+                source: LoopSource::Option(name::short!('I')),
+                body: vec![Command::Add { option: name::short!('I'), values: sv!["${dir}/sub"] }],
+            },
+            Command::ForEach {
+                variable: "f".to_string(),
+                source: LoopSource::Positional,
+                body: vec![Command::AppendPositional { values: sv!["${f}.o"] }],
+            },
+            Command::Set { option: name::short!('o'), values: sv!["${f}"] },
+        ], Vocabulary::parse("I|include")?)?;
+        assert_eq!(args, ParsedArgs {
+            options: vec![
+                short!('I', "a"), long!("include", "b", "c"), short!('v'), short!('I', "a/sub"), short!('I', "b/sub"),
+                short!('I', "c/sub"), short!('o', "outer"),
+            ],
+            positional: sv!["x.c", "y.c", "x.c.o", "y.c.o"],
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_call() -> Result<()> {
+        let call = |value: &str| Command::Call {
+            params: vec![("x".to_string(), value.to_string()), ("y".to_string(), "${x}-y".to_string())],
+            body: vec![
+                Command::Add { option: name::short!('D'), values: sv!["${x}", "${y}"] },
+                Command::Let { name: "x".to_string(), value: "changed".to_string() },
+            ],
+        };
+
+        // Parameters shadow the variables of the caller (including loop variables), and are all expanded before any
+        // of them is set
+        let mut args = ParsedArgs { options: vec![short!('I', "a")], positional: sv![] };
+        apply_all(&mut args, vec![
+            Command::Let { name: "x".to_string(), value: "outer".to_string() },
+            call("inner"),
+            Command::ForEach {
+                variable: "x".to_string(),
+                source: LoopSource::Option(name::short!('I')),
+                body: vec![call("${x}!"), Command::Add { option: name::short!('L'), values: sv!["${x}"] }],
+            },
+            Command::Add { option: name::short!('o'), values: sv!["${x}"] },
+        ])?;
+        assert_eq!(args.options, vec![
+            short!('I', "a"), short!('D', "inner", "outer-y"), short!('D', "a!", "a-y"), short!('L', "a"),
+            short!('o', "outer"),
+        ]);
+
+        // Parameters that did not shadow anything are unset after the call
+        let mut args = ParsedArgs::default();
+        let error = apply_all(&mut args, vec![
+            Command::Call { params: vec![("y".to_string(), "v".to_string())], body: vec![] },
+            Command::Add { option: name::short!('o'), values: sv!["${y}"] },
+        ]).map_err(|e| e.root_cause().to_string());
+        assert_err_contains!(error, "${y} is not defined");
+        Ok(())
+    }
+
+    #[test]
+    fn test_numbers() -> Result<()> {
+        let mut args = ParsedArgs {
+            options: vec![short!('j', "16"), long!("timeout", "10"), short!('j', "a", "32"), long!("retries", "2")],
+            positional: sv![],
+        };
+        apply_all(&mut args, vec![
+            Command::Clamp {
+                option: name::short!('j'),
+                min: Some(Number::parse("1")?),
+                max: Some(Number::parse("8")?),
+            },
+            Command::Clamp { option: name::long!("timeout"), min: Some(Number::parse("30s")?), max: None },
+            Command::Clamp { option: name::long!("missing"), min: Some(Number::parse("1")?), max: None },
+            Command::AddTo { option: name::long!("retries"), amount: Number::parse("1")? },
+        ])?;
+        assert_eq!(args.options, vec![
+            short!('j', "16"), long!("timeout", "30s"), short!('j', "a", "8"), long!("retries", "3"),
+        ]);
+
+        let mut args = ParsedArgs { options: vec![short!('j', "auto"), short!('v')], positional: sv![] };
+        let add_one = |option| Command::AddTo { option, amount: Number::parse("1").unwrap() };
+        let root_cause = |result: Result<()>| result.map_err(|e| e.root_cause().to_string());
+        assert_err_contains!(root_cause(apply_all(&mut args, vec![add_one(name::short!('j'))])), "Not a number: auto");
+        assert_err_contains!(apply_all(&mut args, vec![add_one(name::short!('v'))]), "has no value");
+        Ok(())
+    }
+
+    #[test]
+    fn test_replace() -> Result<()> {
+        let mut args = ParsedArgs {
+            options: vec![short!('I', "/home/build/a", "/home/build/b"), long!("include", "/home/build/c/home/build/")],
+            positional: sv!["file.c", "other.c"],
+        };
+        apply_all_with_vocabulary(&mut args, vec![
+            Command::Replace {
+                option: name::short!('I'),
+                substitution: Substitution::new("/home/build/", "/ws/", "g")?,
+            },
+            Command::ReplacePositional { substitution: Substitution::new(r"(\w+)\.c", "$1.o", "")? },
+        ], Vocabulary::parse("I|include")?)?;
+        assert_eq!(args, ParsedArgs {
+            options: vec![short!('I', "/ws/a", "/ws/b"), long!("include", "/ws/c/ws/")],
+            positional: sv!["file.o", "other.o"],
+        });
+
+        // Only the first match in the last option
+        apply_all(&mut args, vec![
+            Command::Replace { option: name::short!('I'), substitution: Substitution::new("/", "_", "l")? },
+            Command::ReplacePositional { substitution: Substitution::new("o", "c", "l")? },
+        ])?;
+        assert_eq!(args, ParsedArgs {
+            options: vec![short!('I', "_ws/a", "_ws/b"), long!("include", "/ws/c/ws/")],
+            positional: sv!["file.o", "cther.o"],
+        });
+        Ok(())
+    }
+
+    #[test]
+    fn test_transform_values() -> Result<()> {
+        let mut args = ParsedArgs {
+            options: vec![short!('I', "./src/", "/work/lib//"), long!("include", "a/../b"), long!("color", " Auto ")],
+            positional: sv!["/work/Main.c", "/work/out/../Main.o/"],
+        };
+        let normalize = |dir: Option<&str>| Transform::NormalizePath { relative_to: dir.map(Into::into) };
+        apply_all_with_vocabulary(&mut args, vec![
+            Command::TransformValues { option: name::short!('I'), transform: normalize(None) },
+            Command::TransformValues { option: name::long!("color"), transform: Transform::Trim },
+            Command::TransformValues { option: name::long!("color"), transform: Transform::Lower },
+            Command::Let { name: "root".to_string(), value: "/work".to_string() },
+            Command::TransformPositional { transform: normalize(Some("${root}")) },
+            Command::TransformPositional { transform: Transform::Lower },
+        ], Vocabulary::parse("I|include")?)?;
+        assert_eq!(args.options, vec![short!('I', "src", "/work/lib"), long!("include", "b"), long!("color", "auto")]);
+        assert_eq!(args.positional, sv!["main.c", "main.o"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_remove_matching() -> Result<()> {
+        let mut args = ParsedArgs {
+            options: vec![long!("debug-info"), short!('W', "all"), long!("Werror"), long!("debug"), short!('v')],
+            positional: sv![],
+        };
+        apply_all(&mut args, vec![
+            Command::RemoveMatching { pattern: Pattern::from_glob("debug-*")? },
+            Command::RemoveMatching { pattern: Pattern::from_regex("^W")? },
+        ])?;
+        assert_eq!(args, ParsedArgs { options: vec![long!("debug"), short!('v')], positional: sv![] });
+        Ok(())
+    }
+
+    #[test]
+    fn test_positional() -> Result<()> {
+        let mut args = ParsedArgs { options: vec![], positional: sv!["a.c", "b.o", "c.c"] };
+        apply_all(&mut args, vec![
+            Command::InsertPositional { index: 0, values: sv!["first"] },
+            Command::InsertPositional { index: -1, values: sv!["before-last"] },
+            Command::AppendPositional { values: sv!["last", "a.c"] },
+            // Out of range indexes are clamped
+            Command::InsertPositional { index: 100, values: sv!["end"] },
+            Command::InsertPositional { index: -100, values: sv!["start"] },
+        ])?;
+        assert_eq!(args.positional, sv!["start", "first", "a.c", "b.o", "before-last", "c.c", "last", "a.c", "end"]);
+
+        apply_all(&mut args, vec![
+            Command::RemovePositional { index: 0 },
+            Command::RemovePositional { index: -1 },
+            // Missing indexes are ignored
+            Command::RemovePositional { index: 100 },
+            Command::RemovePositional { index: -100 },
+            Command::RemovePositionalValues { values: sv!["a.c", "last"] },
+            Command::RemovePositionalMatching { pattern: Pattern::from_regex(r"\.o$")? },
+        ])?;
+        assert_eq!(args.positional, sv!["first", "before-last", "c.c"]);
+
+        apply_all(&mut args, vec![Command::ClearPositional])?;
+        assert!(args.positional.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_to_empty_value() -> Result<()> {
+        // The delimiter used to be added before every item, so appending to an empty value gave ",x" rather than "x"
+        let append = |option: &str, items: Vec<String>| Command::Append {
+            option: option.parse().unwrap(),
+            delimiter: ",".to_string(),
+            items,
+        };
+        let mut args = ParsedArgs {
+            options: vec![long!("empty", ""), long!("list", "a"), long!("blank", "")],
+            positional: sv![],
+        };
+        apply_all(&mut args, vec![
+            append("empty", sv!["x"]),
+            append("list", sv!["x", "y"]),
+            append("missing", sv!["x"]),
+            // Empty items are still delimited
+            append("blank", sv!["", "x"]),
+            append("list", sv![]),
+        ])?;
+        assert_eq!(args.options, vec![
+            long!("empty", "x"),
+            long!("list", "a,x,y"),
+            long!("blank", ",x"),
+            long!("missing", "x"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_and_prepend() -> Result<()> {
+        let mut args = ParsedArgs { options: vec![long!("path", "/bin")], positional: sv![] };
+        apply_all(&mut args, vec![
+            Command::Prepend { option: name::long!("path"), delimiter: ":".to_string(), items: sv!["/a", "/b"] },
+            Command::Append { option: name::long!("path"), delimiter: ":".to_string(), items: sv!["/c"] },
+            // The delimiter is not added to empty values
+            Command::Append { option: name::long!("list"), delimiter: ",".to_string(), items: sv!["x", "y"] },
+            Command::Prepend { option: name::long!("other"), delimiter: ",".to_string(), items: sv!["z"] },
+            Command::Prepend { option: name::long!("name"), delimiter: "".to_string(), items: sv!["pre", "fix-"] },
+        ])?;
+        assert_eq!(args.options, vec![
+            long!("path", "/a:/b:/bin:/c"),
+            long!("list", "x,y"),
+            long!("other", "z"),
+            long!("name", "prefix-"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_unappend() -> Result<()> {
+        let unappend = |option: &str, items: ValueSelector, drop_empty: bool| Command::Unappend {
+            option: option.parse().unwrap(),
+            delimiter: ",".to_string(),
+            items,
+            drop_empty,
+        };
+
+        let mut args = ParsedArgs {
+            options: vec![long!("features", "a,b"), long!("features", "a,b,c,b"), long!("other", "x")],
+            positional: sv![],
+        };
+        apply_all(&mut args, vec![
+            unappend("features", ValueSelector::Exact(sv!["b", "missing"]), false),
+            unappend("other", ValueSelector::Matching(Pattern::from_regex("^x$")?), false),
+            unappend("missing", ValueSelector::Exact(sv!["a"]), true),
+        ])?;
+        assert_eq!(args.options, vec![long!("features", "a,b"), long!("features", "a,c"), long!("other", "")]);
+
+        let pattern = Pattern::from_regex("^[ac]$")?;
+        apply_all(&mut args, vec![unappend("features", ValueSelector::Matching(pattern), true)])?;
+        assert_eq!(args.options, vec![long!("features", "a,b"), long!("other", "")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedupe() -> Result<()> {
+        let options = vec![
+            short!('I', "a"),
+            long!("include", "b"),
+            long!("define", "X"),
+            long!("include", "a"),
+            short!('I', "b"),
+            long!("define", "X"),
+            long!("define", "Y"),
+        ];
+        let vocabulary = || Vocabulary::parse("I|include");
+
+        let mut args = ParsedArgs { options: options.clone(), positional: sv![] };
+        apply_all_with_vocabulary(&mut args, vec![
+            Command::Dedupe { option: None, keep: Occurrence::First },
+        ], vocabulary()?)?;
+        assert_eq!(args.options, vec![
+            short!('I', "a"),
+            long!("include", "b"),
+            long!("define", "X"),
+            long!("define", "Y"),
+        ]);
+
+        let mut args = ParsedArgs { options: options.clone(), positional: sv![] };
+        apply_all_with_vocabulary(&mut args, vec![
+            Command::Dedupe { option: Some(name::long!("include")), keep: Occurrence::Last },
+        ], vocabulary()?)?;
+        assert_eq!(args.options, vec![
+            long!("define", "X"),
+            long!("include", "a"),
+            short!('I', "b"),
+            long!("define", "X"),
+            long!("define", "Y"),
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_dedupe_values() -> Result<()> {
+        let mut args = ParsedArgs { options: vec![long!("path", "/a:/b:/a:/c:/b")], positional: sv![] };
+        let dedupe_values = |keep| Command::DedupeValues {
+            option: name::long!("path"),
+            delimiter: ":".to_string(),
+            keep,
+        };
+
+        apply_all(&mut args, vec![dedupe_values(Occurrence::Last)])?;
+        assert_eq!(args.options, vec![long!("path", "/a:/c:/b")]);
+
+        args.options = vec![long!("path", "/a:/b:/a:/c:/b")];
+        apply_all(&mut args, vec![dedupe_values(Occurrence::First)])?;
+        assert_eq!(args.options, vec![long!("path", "/a:/b:/c")]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_explode_and_implode() -> Result<()> {
+        let vocabulary = Vocabulary::parse("I|include repeated\nfeatures delimited=,\nfiles variadic")?;
+        let mut args = ParsedArgs {
+            options: vec![long!("features", "a,b"), short!('I', "x", "y"), short!('v'), long!("features", "c")],
+            positional: sv![],
+        };
+
+        let explode_features = vec![Command::Explode { option: name::long!("features"), delimiter: None }];
+        apply_all_with_vocabulary(&mut args, explode_features, vocabulary.clone())?;
+        assert_eq!(args.options, vec![
+            long!("features", "a"), long!("features", "b"), short!('I', "x", "y"), short!('v'), long!("features", "c"),
+        ]);
+
+        apply_all_with_vocabulary(&mut args, vec![
+            Command::Implode { option: name::long!("features"), delimiter: None },
+            Command::Explode { option: name::long!("include"), delimiter: None },
+        ], vocabulary.clone())?;
+        assert_eq!(args.options, vec![long!("features", "a,b,c"), short!('I', "x"), short!('I', "y"), short!('v')]);
+
+        apply_all_with_vocabulary(&mut args, vec![
+            Command::Implode { option: name::short!('I'), delimiter: Some(":".to_string()) },
+            Command::Explode { option: name::long!("features"), delimiter: Some(",".to_string()) },
+            Command::Implode { option: name::long!("features"), delimiter: Some(";".to_string()) },
+        ], vocabulary.clone())?;
+        assert_eq!(args.options, vec![long!("features", "a;b;c"), short!('I', "x:y"), short!('v')]);
+
+        let mut args = ParsedArgs {
+            options: vec![long!("files", "a"), short!('v'), long!("other", "b"), long!("files", "c", "d")],
+            positional: sv![],
+        };
+        apply_all_with_vocabulary(&mut args, vec![
+            Command::Implode { option: name::long!("files"), delimiter: None },
+            Command::Implode { option: name::long!("other"), delimiter: None },
+            Command::Implode { option: name::long!("missing"), delimiter: None },
+        ], vocabulary.clone())?;
+        assert_eq!(args.options, vec![long!("files", "a", "c", "d"), short!('v'), long!("other", "b")]);
+
+        let implode_include = vec![Command::Implode { option: name::long!("include"), delimiter: None }];
+        assert_err_contains!(apply_all_with_vocabulary(&mut args, implode_include, vocabulary),
+                             "needs a delimiter");
+        assert_err_contains!(apply_all(&mut args, vec![
+            Command::Explode { option: name::long!("files"), delimiter: Some("".to_string()) },
+        ]), "cannot be empty");
+        Ok(())
+    }
+
+    #[test]
+    fn test_interpolation() -> Result<()> {
+        let mut args = ParsedArgs { options: vec![long!("name", "main")], positional: sv![] };
+        apply_all(&mut args, vec![
+            Command::Let { name: "out".to_string(), value: "${OCO_TEST_UNSET_OUT_DIR:-build}".to_string() },
+            Command::Set { option: name::long!("output"), values: sv!["${out}/${opt:name}.o"] },
+            Command::AppendPositional { values: sv!["${opt:output}"] },
+        ])?;
+        assert_eq!(args, ParsedArgs {
+            options: vec![long!("name", "main"), long!("output", "build/main.o")],
+            positional: sv!["build/main.o"],
+        });
+
+        let missing = Command::Add { option: name::long!("x"), values: sv!["${OCO_TEST_UNSET_VARIABLE}"] };
+        assert_err_contains!(apply_all(&mut args, vec![missing]), "is not defined");
+        Ok(())
+    }
+
+    #[test]
+    fn test_assert() -> Result<()> {
+        let require_output = || Command::Assert {
+            condition: Condition::Has(name::short!('o')),
+            message: "Missing ${opt:name:-output}".to_string(),
+        };
+
+        let mut args = ParsedArgs { options: vec![short!('o', "a.out")], positional: sv![] };
+        apply_all(&mut args, vec![require_output()])?;
+        assert_eq!(args, ParsedArgs { options: vec![short!('o', "a.out")], positional: sv![] });
+
+        let mut args = ParsedArgs::default();
+        let error = apply_all(&mut args, vec![require_output()]).unwrap_err();
+        assert_eq!(error.downcast_ref::<AssertionError>().map(|e| e.0.as_str()), Some("Missing output"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_exclusive() -> Result<()> {
+        let vocabulary = Vocabulary::parse("q|quiet\nv|verbose")?;
+        let exclusive = || Command::Exclusive { options: vec![name::long!("quiet"), name::long!("verbose")] };
+
+        let mut args = ParsedArgs { options: vec![short!('v'), long!("verbose"), short!('a')], positional: sv![] };
+        apply_all_with_vocabulary(&mut args, vec![exclusive(), Command::CheckExclusive], vocabulary.clone())?;
+
+        apply_all_with_vocabulary(&mut args, vec![
+            exclusive(),
+            Command::Set { option: name::short!('q'), values: sv![] },
+            Command::CheckExclusive,
+        ], vocabulary.clone())?;
+        assert_eq!(args.options, vec![short!('a'), short!('q')]);
+
+        // Copying and deriving set options in the same way
+        let mut args = ParsedArgs { options: vec![short!('v'), long!("level", "2")], positional: sv![] };
+        apply_all_with_vocabulary(&mut args, vec![
+            exclusive(),
+            Command::Derive {
+                option: name::long!("quiet"),
+                template: "${opt:level}".to_string(),
+                transforms: vec![],
+                add: false,
+            },
+            Command::Copy { from: name::long!("level"), to: name::long!("verbose") },
+            Command::CheckExclusive,
+        ], vocabulary.clone())?;
+        assert_eq!(args.options, vec![long!("level", "2"), long!("verbose", "2")]);
+
+        let mut args = ParsedArgs { options: vec![long!("verbose"), short!('a'), short!('q')], positional: sv![] };
+        let error = apply_all_with_vocabulary(&mut args, vec![exclusive(), Command::CheckExclusive], vocabulary)
+                .unwrap_err();
+        assert_eq!(error.downcast_ref::<AssertionError>().map(|e| e.0.as_str()),
+                   Some("Options cannot be used together: q, verbose"));
+        Ok(())
+    }
+}
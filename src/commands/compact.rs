@@ -2,13 +2,14 @@ use std::borrow::Cow;
 use std::io::BufRead;
 use eyre::{bail, Context, ContextCompat, eyre, Result};
 use itertools::Itertools;
-use pest::iterators::Pair;
+use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest_derive::Parser;
 use tap::Pipe;
 
 use crate::commands::common::Command;
 use crate::commands::common::CommandParser;
+use crate::commands::{Pattern, Substitution, ValueSelector};
 use crate::commands::parser_exts::{InnerUnwrap, PairExt, PairsExt, SupportsQuoting};
 use crate::opts::parsed_args::OptName;
 
@@ -36,7 +37,7 @@ impl CommandParser for CompactCommandParser {
         self.parse_from_args(lines)
     }
 
-    fn parse_from_args<'a, I, S>(&self, commands: I) -> Result<Vec<Command>>
+    fn parse_from_args<I, S>(&self, commands: I) -> Result<Vec<Command>>
         where S: AsRef<str>,
               I: Iterator<Item=S>
     {
@@ -50,6 +51,67 @@ impl CommandParser for CompactCommandParser {
     }
 }
 
+/// Parses an option name, where a `--` prefix makes it long even if it has a single character
+fn parse_option_name(option_name: Pair<Rule>) -> Result<OptName> {
+    let mut parts = option_name.into_inner();
+    let prefix = parts.attempt_rule(Rule::long_prefix).map_or("", |prefix| prefix.as_str());
+    let name = parts.next().wrap_err("Missing option name")?.parse_quoted()?; // quoted or ident
+    format!("{prefix}{name}").parse()
+}
+
+fn parse_positional_command(command_pair: Pair<Rule>) -> Result<Option<Command>> {
+    let rule = command_pair.as_rule();
+    let mut matches = command_pair.into_inner();
+    let command = match rule {
+        Rule::pos_replace_command => {
+            Command::ReplacePositional { substitution: parse_substitution(matches.expect_rule(Rule::substitution)?)? }
+        }
+        Rule::pos_append_command => {
+            Command::AppendPositional { values: parse_values(matches.expect_rule(Rule::add_values)?)? }
+        }
+        Rule::pos_prepend_command => {
+            Command::InsertPositional { index: 0, values: parse_values(matches.expect_rule(Rule::add_values)?)? }
+        }
+        Rule::pos_insert_command => {
+            let index = parse_index(matches.expect_rule(Rule::pos_index)?)?;
+            Command::InsertPositional { index, values: parse_values(matches.expect_rule(Rule::add_values)?)? }
+        }
+        Rule::pos_clear_command => Command::ClearPositional,
+        Rule::pos_remove_command => {
+            let target = matches.expect_rules(&[Rule::add_values, Rule::name_regex, Rule::pos_index])?;
+            match target.as_rule() {
+                Rule::name_regex => Command::RemovePositionalMatching { pattern: parse_pattern(target)? },
+                Rule::pos_index => Command::RemovePositional { index: parse_index(target)? },
+                _ => Command::RemovePositionalValues { values: parse_values(target)? },
+            }
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some(command))
+}
+
+fn parse_values(values: Pair<Rule>) -> Result<Vec<String>> {
+    let values = parse_value_clause(values)?;
+    Ok(values.args.into_iter().map(Cow::into_owned).collect_vec())
+}
+
+fn parse_pattern(name_regex: Pair<Rule>) -> Result<Pattern> {
+    Pattern::from_regex(name_regex.into_inner().expect_rule(Rule::regex_pattern)?.as_str())
+}
+
+fn parse_index(pos_index: Pair<Rule>) -> Result<isize> {
+    pos_index.as_str().parse().wrap_err_with(|| format!("Invalid index: {}", pos_index.as_str()))
+}
+
+fn parse_substitution(substitution: Pair<Rule>) -> Result<Substitution> {
+    let mut matches = substitution.into_inner();
+    Substitution::new(
+        matches.expect_rule(Rule::regex_pattern)?.as_str(),
+        matches.expect_rule(Rule::regex_replacement)?.as_str(),
+        matches.expect_rule(Rule::regex_flags)?.as_str(),
+    )
+}
+
 fn parse_command(command: &str) -> Result<Command> {
     let command_pair = InternalParser::parse(Rule::command_input, command)
             .wrap_err("Bad syntax for compact command")?
@@ -58,17 +120,41 @@ fn parse_command(command: &str) -> Result<Command> {
             .map_single_wrapped() // typed command
             .wrap_err("Missing typed command")?;
 
+    if let Some(command) = parse_positional_command(command_pair.clone())? {
+        return Ok(command);
+    }
+
     let mut matches = command_pair.clone().into_inner();
-    let option: OptName = matches.expect_rule(Rule::option_name)?
-            .parse_quoted()?
-            .try_into()?;
+    if let Some(name) = matches.attempt_rule(Rule::variable_name) {
+        let value = matches.expect_rule(Rule::raw_value_arg)?.as_str().to_string();
+        return Ok(Command::Let { name: name.as_str().to_string(), value });
+    }
+    if let Some(regex) = matches.attempt_rule(Rule::name_regex) {
+        return Ok(Command::RemoveMatching { pattern: parse_pattern(regex)? });
+    }
+    if let Some(glob) = matches.attempt_rule(Rule::name_glob) {
+        let glob = glob.into_inner().expect_rule(Rule::quoted)?.parse_quoted_into_string()?;
+        return Ok(Command::RemoveMatching { pattern: Pattern::from_glob(&glob)? });
+    }
+
+    let option = parse_option_name(matches.expect_rule(Rule::option_name)?)?;
+    if let Some(unappend_mod) = matches.attempt_rule(Rule::unappend_mod) {
+        return parse_unappend(option, unappend_mod, matches);
+    }
+    let new_name = matches.attempt_rule(Rule::option_name)
+            .map(parse_option_name)
+            .transpose()?;
+    let substitution = matches.attempt_rule(Rule::substitution)
+            .map(parse_substitution)
+            .transpose()?;
 
     let value_rules = [Rule::set_values, Rule::add_values, Rule::repeat_values];
-    let values = matches
+    let value_clause = matches
             .attempt_rules(&value_rules)
             .map(parse_value_clause)
-            .transpose()?
-            .unwrap_or_default();
+            .transpose()?;
+    let has_values = value_clause.is_some();
+    let values = value_clause.unwrap_or_default();
 
     // Convert vector items from Cow<str> to String (using move when possible)
     let args = values.args.into_iter().map(Cow::into_owned).collect_vec();
@@ -80,22 +166,53 @@ fn parse_command(command: &str) -> Result<Command> {
                 delimiter: delimiter.unwrap_or("".into()).into_owned(),
                 items: args,
             },
+        (Rule::set_command, Modifier::Prepend { delimiter }) =>
+            Command::Prepend {
+                option,
+                delimiter: delimiter.unwrap_or("".into()).into_owned(),
+                items: args,
+            },
         (Rule::set_command, _) => Command::Set { option, values: args },
         (Rule::add_command, _) => Command::Add { option, values: args },
         (Rule::remove_command, _) => Command::Remove { option },
+        (Rule::default_command, _) => Command::Default { option, values: args },
         (Rule::repeat_command, _) => Command::RepeatedAdd { option, values: args },
+        (Rule::replace_command, _) => Command::Replace {
+            option,
+            substitution: substitution.wrap_err("Missing substitution")?,
+        },
+        (Rule::rename_command, _) => Command::Rename {
+            option,
+            new_name: new_name.wrap_err("Missing new option name")?,
+            values: has_values.then_some(args),
+        },
         (_, _) => bail!("Unknown command rule detected: {:?}", &command_pair.as_rule()),
     };
 
     Ok(command)
 }
 
+fn parse_unappend(option: OptName, unappend_mod: Pair<Rule>, mut matches: Pairs<Rule>) -> Result<Command> {
+    let mut modifiers = unappend_mod.into_inner();
+    let delimiter = modifiers.expect_rule(Rule::append_delimiter)?
+            .map_single_wrapped()
+            .wrap_err("Missing delimiter")?
+            .parse_quoted_into_string()?;
+    let drop_empty = modifiers.attempt_rule(Rule::drop_empty_mod).is_some();
+
+    let items = match matches.expect_rules(&[Rule::value_args, Rule::name_regex])? {
+        regex if regex.as_rule() == Rule::name_regex => ValueSelector::Matching(parse_pattern(regex)?),
+        values => ValueSelector::Exact(parse_value_args(values)?.into_iter().map(Cow::into_owned).collect_vec()),
+    };
+    Ok(Command::Unappend { option, delimiter, items, drop_empty })
+}
+
 fn parse_value_clause(values_clause: Pair<Rule>) -> Result<ValueClause> {
     let mut matches = values_clause.clone().into_inner();
 
     // Parse the modifier first (coming before the '=' sign)
     let modifier: Modifier = matches
-            .attempt_rules(&[Rule::append_mod, Rule::multi_arg_mod])
+            .attempt_rules(&[Rule::append_mod, Rule::prepend_mod, Rule::multi_arg_mod])
             .pipe(parse_modifier)?;
 
     // Parse value args (coming after the '=' sign)
@@ -107,18 +224,24 @@ fn parse_value_clause(values_clause: Pair<Rule>) -> Result<ValueClause> {
             // Collect the single argument into a vector
             vec![value_args_pair.as_str().into()]
         }
-        Rule::value_args => {
-            // Collect space-separated, quotable, arguments into a vector
-            value_args_pair
-                    .into_inner().filter(|pair| pair.as_rule() == Rule::value_arg)
-                    .map(|pair| pair.parse_quoted()).try_collect()?
-        }
+        Rule::value_args => parse_value_args(value_args_pair)?,
         _ => bail!("Unexpected rule for arguments: {:?}", value_args_pair.as_rule()),
     };
 
     Ok(ValueClause { modifier, args })
 }
 
+/// Collects space-separated, quotable, arguments into a vector
+fn parse_value_args(value_args: Pair<Rule>) -> Result<Vec<Cow<str>>> {
+    value_args
+            .into_inner().filter(|pair| pair.as_rule() == Rule::value_arg)
+            .map(|pair| pair
+                    .map_single_wrapped() // quoted or value
+                    .wrap_err("Expected quotable value")?
+                    .parse_quoted())
+            .try_collect()
+}
+
 fn parse_modifier(modifier: Option<Pair<Rule>>) -> Result<Modifier> {
     let Some(modifier) = modifier else {
         return Ok(Modifier::None);
@@ -135,6 +258,16 @@ fn parse_modifier(modifier: Option<Pair<Rule>>) -> Result<Modifier> {
 
             Modifier::Append { delimiter }
         }
+        Rule::prepend_mod => {
+            let delimiter = modifier.into_inner()
+                    .attempt_rule(Rule::append_delimiter)
+                    .and_then(|pair| pair.map_single_wrapped())
+                    .map(|pair| pair.parse_quoted())
+                    .transpose()
+                    .wrap_err_with(|| eyre!("Bad prepend delimiter"))?;
+
+            Modifier::Prepend { delimiter }
+        }
         Rule::multi_arg_mod => Modifier::MultiArg,
         _ => bail!("Unexpected modifier '{}'", modifier.as_str()),
     };
@@ -148,6 +281,7 @@ enum Modifier<'i> {
     None,
     MultiArg,
     Append { delimiter: Option<Cow<'i, str>> },
+    Prepend { delimiter: Option<Cow<'i, str>> },
 }
 
 #[derive(Default, Debug, Clone, Eq, PartialEq)]
@@ -163,3 +297,138 @@ impl SupportsQuoting for Rule {
     }
 }
 
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::commands::{Command, CommandParser, Pattern, Substitution, ValueSelector};
+    use crate::commands::compact::CompactCommandParser;
+    use crate::util::testing::opts::name::{long, short};
+    use crate::util::testing::opts::sv;
+
+    fn parse(commands: &[&str]) -> Result<Vec<Command>> {
+        CompactCommandParser.parse_from_args(commands.iter())
+    }
+
+    #[test]
+    fn test_parse_single_char_long_names() -> Result<()> {
+        assert_eq!(parse(&["--O=3", "---O", "+--O/=1 2", "-'--v'", "O>--O"])?, vec![
+            Command::Set { option: long!("O"), values: sv!["3"] },
+            Command::Remove { option: long!("O") },
+            Command::Add { option: long!("O"), values: sv!["1", "2"] },
+            Command::Remove { option: long!("v") },
+            Command::Rename { option: short!('O'), new_name: long!("O"), values: None },
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_rename() -> Result<()> {
+        assert_eq!(parse(&["verbose>log-level=debug", "v>quiet", "a>b/=1 2"])?, vec![
+            Command::Rename { option: long!("verbose"), new_name: long!("log-level"), values: Some(sv!["debug"]) },
+            Command::Rename { option: short!('v'), new_name: long!("quiet"), values: None },
+            Command::Rename { option: short!('a'), new_name: short!('b'), values: Some(sv!["1", "2"]) },
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_default() -> Result<()> {
+        assert_eq!(parse(&["?color=auto", "?v", "?a/=1 2"])?, vec![
+            Command::Default { option: long!("color"), values: sv!["auto"] },
+            Command::Default { option: short!('v'), values: sv![] },
+            Command::Default { option: short!('a'), values: sv!["1", "2"] },
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_replace() -> Result<()> {
+        assert_eq!(parse(&[r"I~/^\/home\/build\//\/workspace\//g", "@~/x//l"])?, vec![
+            Command::Replace {
+                option: short!('I'),
+                substitution: Substitution::new("^/home/build/", "/workspace/", "g")?,
+            },
+            Command::ReplacePositional { substitution: Substitution::new("x", "", "l")? },
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_remove() -> Result<()> {
+        assert_eq!(parse(&["-v", "-'long name'", "-'debug-*'", "-~'debug-*'", "-/^W/"])?, vec![
+            Command::Remove { option: short!('v') },
+            Command::Remove { option: long!("long name") },
+            Command::Remove { option: long!("debug-*") },
+            Command::RemoveMatching { pattern: Pattern::from_glob("debug-*")? },
+            Command::RemoveMatching { pattern: Pattern::from_regex("^W")? },
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_positional() -> Result<()> {
+        assert_eq!(parse(&["@^=./src", "@+/=a 'b c'", "@-1+=x", "@-0", "@--2", "@-=0", r"@-/\.o$/", "@-*"])?, vec![
+            Command::InsertPositional { index: 0, values: sv!["./src"] },
+            Command::AppendPositional { values: sv!["a", "b c"] },
+            Command::InsertPositional { index: -1, values: sv!["x"] },
+            Command::RemovePositional { index: 0 },
+            Command::RemovePositional { index: -2 },
+            Command::RemovePositionalValues { values: sv!["0"] },
+            Command::RemovePositionalMatching { pattern: Pattern::from_regex(r"\.o$")? },
+            Command::ClearPositional,
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_unappend_and_prepend() -> Result<()> {
+        let commands = ["features-,=b", "path-':'!=/bin 'a b'", "list-;~/^x/", "path^:=/opt/bin", "name^=x-"];
+        assert_eq!(parse(&commands)?, vec![
+            Command::Unappend {
+                option: long!("features"),
+                delimiter: ",".to_string(),
+                items: ValueSelector::Exact(sv!["b"]),
+                drop_empty: false,
+            },
+            Command::Unappend {
+                option: long!("path"),
+                delimiter: ":".to_string(),
+                items: ValueSelector::Exact(sv!["/bin", "a b"]),
+                drop_empty: true,
+            },
+            Command::Unappend {
+                option: long!("list"),
+                delimiter: ";".to_string(),
+                items: ValueSelector::Matching(Pattern::from_regex("^x")?),
+                drop_empty: false,
+            },
+            Command::Prepend { option: long!("path"), delimiter: ":".to_string(), items: sv!["/opt/bin"] },
+            Command::Prepend { option: long!("name"), delimiter: "".to_string(), items: sv!["x-"] },
+        ]);
+
+        // Names with dashes are still supported, including trailing ones
+        assert_eq!(parse(&["log-level=debug", "trailing-=x", "-trailing-", "+trailing--=y", "list--,=a"])?, vec![
+            Command::Set { option: long!("log-level"), values: sv!["debug"] },
+            Command::Set { option: long!("trailing-"), values: sv!["x"] },
+            Command::Remove { option: long!("trailing-") },
+            Command::Add { option: long!("trailing--"), values: sv!["y"] },
+            Command::Unappend {
+                option: long!("list-"),
+                delimiter: ",".to_string(),
+                items: ValueSelector::Exact(sv!["a"]),
+                drop_empty: false,
+            },
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_variables() -> Result<()> {
+        assert_eq!(parse(&["$out=${OUT_DIR:-build}", "output/=${out}/a.o '${x}'"])?, vec![
+            Command::Let { name: "out".to_string(), value: "${OUT_DIR:-build}".to_string() },
+            Command::Set { option: long!("output"), values: sv!["${out}/a.o", "${x}"] },
+        ]);
+        Ok(())
+    }
+}
@@ -0,0 +1,100 @@
+use eyre::Result;
+
+use crate::commands::{Context, Pattern};
+use crate::opts::parsed_args::{OptName, ParsedArgs};
+
+/// A condition for conditional blocks and assertions, evaluated against the arguments at the time it is applied
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Condition {
+    /// The option is present (under any of its names)
+    Has(OptName),
+    /// The last occurrence of the option has exactly the given value
+    Equals(OptName, String),
+    /// The option is present, and all the values of all of its occurrences match the pattern
+    Matches(OptName, Pattern),
+    /// The number of positional arguments is within the (inclusive) range
+    PositionalCount { min: usize, max: Option<usize> },
+    Not(Box<Condition>),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+}
+
+impl Condition {
+    pub fn evaluate(&self, args: &ParsedArgs, ctx: &Context) -> Result<bool> {
+        let result = match self {
+            Condition::Has(option) => args.find_last(ctx.vocabulary.aliases(option)).is_some(),
+            Condition::Equals(option, value) => {
+                let value = ctx.interpolate(value, args)?;
+                args.find_last(ctx.vocabulary.aliases(option)).is_some_and(|found| found.values == [value])
+            }
+            Condition::Matches(option, pattern) => {
+                let mut found = args.find_all(ctx.vocabulary.aliases(option)).peekable();
+                found.peek().is_some() && found.flat_map(|o| &o.values).all(|value| pattern.is_match(value))
+            }
+            Condition::PositionalCount { min, max } => {
+                let count = args.positional.len();
+                count >= *min && max.is_none_or(|max| count <= max)
+            }
+            Condition::Not(condition) => !condition.evaluate(args, ctx)?,
+            Condition::And(conditions) => {
+                for condition in conditions {
+                    if !condition.evaluate(args, ctx)? {
+                        return Ok(false);
+                    }
+                }
+                true
+            }
+            Condition::Or(conditions) => {
+                for condition in conditions {
+                    if condition.evaluate(args, ctx)? {
+                        return Ok(true);
+                    }
+                }
+                false
+            }
+        };
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cli::Dialect;
+    use crate::commands::{Context, Pattern};
+    use crate::commands::condition::Condition::{self, And, Equals, Has, Matches, Not, Or, PositionalCount};
+    use crate::opts::grammar::Gnu;
+    use crate::opts::parsed_args::ParsedArgs;
+    use crate::opts::vocabulary::Vocabulary;
+    use crate::util::testing::opts::name::{long, short};
+    use crate::util::testing::opts::parsed;
+    use crate::util::testing::opts::sv;
+
+    #[test]
+    fn test_evaluate() -> eyre::Result<()> {
+        let args = ParsedArgs {
+            options: vec![parsed::long!("ci"), parsed::long!("color", "never"), parsed::long!("color", "auto")],
+            positional: sv!["a", "b"],
+        };
+        let ctx = Context::new(Dialect::Gnu(Gnu::default()), Vocabulary::parse("c|ci")?);
+        let evaluate = |condition: Condition| condition.evaluate(&args, &ctx).unwrap();
+
+        assert!(evaluate(Has(short!('c'))));
+        assert!(!evaluate(Has(long!("verbose"))));
+        assert!(evaluate(Equals(long!("color"), "auto".to_string())));
+        assert!(!evaluate(Equals(long!("color"), "never".to_string())));
+        assert!(!evaluate(Equals(long!("ci"), "".to_string())));
+        assert!(evaluate(Not(Box::new(Has(long!("verbose"))))));
+        assert!(evaluate(And(vec![Has(long!("ci")), Has(long!("color"))])));
+        assert!(!evaluate(And(vec![Has(long!("ci")), Has(long!("verbose"))])));
+        assert!(evaluate(Or(vec![Has(long!("verbose")), Has(long!("ci"))])));
+        assert!(!evaluate(Or(vec![Has(long!("verbose")), Has(long!("quiet"))])));
+
+        assert!(evaluate(Matches(long!("color"), Pattern::from_regex("^(auto|never)$")?)));
+        assert!(!evaluate(Matches(long!("color"), Pattern::from_regex("^auto$")?)));
+        assert!(!evaluate(Matches(long!("verbose"), Pattern::from_regex("")?)));
+        assert!(evaluate(PositionalCount { min: 1, max: Some(2) }));
+        assert!(evaluate(PositionalCount { min: 2, max: None }));
+        assert!(!evaluate(PositionalCount { min: 0, max: Some(1) }));
+        Ok(())
+    }
+}
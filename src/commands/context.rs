@@ -0,0 +1,141 @@
+use std::collections::HashMap;
+use std::env;
+use std::env::VarError;
+use derive_new::new;
+use eyre::{bail, ContextCompat, Result};
+use itertools::Itertools;
+
+use crate::cli::Dialect;
+use crate::opts::parsed_args::{OptName, ParsedArgs};
+use crate::opts::vocabulary::Vocabulary;
+
+/// State that is shared by all commands while they are applied
+#[derive(new, Clone, Debug)]
+pub struct Context {
+    /// The dialect used for parsing arguments that do not come from the input (e.g. from environment variables)
+    pub dialect: Dialect,
+
+    /// Known options, used for treating all the names of an option alike
+    pub vocabulary: Vocabulary,
+
+    /// Variables defined by the script (with `let`), which take precedence over environment variables
+    #[new(default)]
+    pub variables: HashMap<String, String>,
+
+    /// Looks up environment variables (tests replace it, so that they don't depend on the process environment)
+    #[new(value = "|name| env::var(name)")]
+    pub env: fn(&str) -> Result<String, VarError>,
+}
+
+impl Context {
+    /// Expands the references in a value:
+    /// - `${NAME}`: a script variable, or an environment variable
+    /// - `${opt:NAME}`: the values of the last occurrence of an option (separated by spaces)
+    /// - `${NAME:-default}`: a reference with a default, for when it is missing
+    ///
+    /// `$${` stands for a literal `${`.
+    pub fn interpolate(&self, value: &str, args: &ParsedArgs) -> Result<String> {
+        let mut result = String::with_capacity(value.len());
+        let mut rest = value;
+        while let Some(start) = rest.find('$') {
+            result.push_str(&rest[..start]);
+            rest = &rest[start..];
+
+            if let Some(escaped) = rest.strip_prefix("$${") {
+                result.push_str("${");
+                rest = escaped;
+            } else if let Some(reference) = rest.strip_prefix("${") {
+                let end = reference.find('}')
+                        .wrap_err_with(|| format!("Missing '}}' in reference: {value}"))?;
+                result.push_str(&self.resolve(&reference[..end], args)?);
+                rest = &reference[end + 1..];
+            } else {
+                result.push('$');
+                rest = &rest[1..];
+            }
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+
+    pub fn interpolate_all(&self, values: Vec<String>, args: &ParsedArgs) -> Result<Vec<String>> {
+        values.iter().map(|value| self.interpolate(value, args)).collect()
+    }
+
+    fn resolve(&self, reference: &str, args: &ParsedArgs) -> Result<String> {
+        // The `--` prefix of an option name (e.g. `${opt:--O}`) is not the start of a default
+        let option = reference.strip_prefix("opt:");
+        let name = option.unwrap_or(reference);
+        let name_start = if option.is_some() && name.starts_with("--") { 2 } else { 0 };
+        let (name, default) = match name[name_start..].find(":-") {
+            Some(end) => (&name[..name_start + end], Some(&name[name_start + end + 2..])),
+            None => (name, None),
+        };
+
+        let resolved = match option {
+            Some(_) => {
+                let option = name.parse::<OptName>()?;
+                args.find_last(self.vocabulary.aliases(&option)).map(|found| found.values.iter().join(" "))
+            }
+            None => self.variables.get(name).cloned().or_else(|| (self.env)(name).ok()),
+        };
+
+        match (resolved, default) {
+            (Some(resolved), _) => Ok(resolved),
+            (None, Some(default)) => Ok(default.to_string()),
+            (None, None) if option.is_some() => bail!("Option referenced in ${{{reference}}} is not present"),
+            (None, None) => bail!("Variable referenced in ${{{reference}}} is not defined"),
+        }
+    }
+}
+
+/// Escapes the references in a value, so that interpolating it gives the value as is
+pub fn escape_references(value: &str) -> String {
+    value.replace("${", "$${")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env::VarError;
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::cli::Dialect;
+    use crate::commands::Context;
+    use crate::commands::context::escape_references;
+    use crate::opts::grammar::Gnu;
+    use crate::opts::parsed_args::ParsedArgs;
+    use crate::opts::vocabulary::Vocabulary;
+    use crate::util::testing::assert_err_contains;
+    use crate::util::testing::opts::parsed::{long, short};
+    use crate::util::testing::opts::sv;
+
+    #[test]
+    fn test_interpolate() -> Result<()> {
+        let mut ctx = Context::new(Dialect::Gnu(Gnu::default()), Vocabulary::parse("n|name")?);
+        ctx.env = |name| if name == "PREFIX" { Ok("/env".to_string()) } else { Err(VarError::NotPresent) };
+        ctx.variables.insert("dir".to_string(), "/var".to_string());
+        let args = ParsedArgs {
+            options: vec![long!("name", "main"), short!('D', "a", "b"), short!('v')],
+            positional: sv![],
+        };
+
+        assert_eq!(ctx.interpolate("${PREFIX}/${opt:n}.o", &args)?, "/env/main.o");
+        assert_eq!(ctx.interpolate("${dir}:${opt:D}:${opt:v}", &args)?, "/var:a b:");
+        assert_eq!(ctx.interpolate("${UNSET:-x-y}${opt:missing:-}", &args)?, "x-y");
+        assert_eq!(ctx.interpolate("$HOME $$ $${dir} a$", &args)?, "$HOME $$ ${dir} a$");
+
+        // A `--` prefix makes a single-character option name long, rather than starting a default
+        let long_o = ParsedArgs { options: vec![short!('O', "1"), long!("O", "2")], positional: sv![] };
+        assert_eq!(ctx.interpolate("${opt:--O}/${opt:O}/${opt:--O:-x}", &long_o)?, "2/1/2");
+        assert_eq!(ctx.interpolate("${opt:--O:-x}/${opt:O:--y}", &args)?, "x/-y");
+        assert_err_contains!(ctx.interpolate("${opt:--O}", &args), "is not present");
+        for value in ["${dir}", "$${dir}", "$$${opt:n}"] {
+            assert_eq!(ctx.interpolate(&escape_references(value), &args)?, value);
+        }
+
+        assert_err_contains!(ctx.interpolate("${UNSET}", &args), "is not defined");
+        assert_err_contains!(ctx.interpolate("${opt:missing}", &args), "is not present");
+        assert_err_contains!(ctx.interpolate("${dir", &args), "Missing '}'");
+        Ok(())
+    }
+}
@@ -1,20 +1,30 @@
+use std::collections::HashMap;
+use std::fs;
 use std::io::BufRead;
+use std::path::{Path, PathBuf};
 use eyre::{bail, Context, ContextCompat, eyre, Result};
 use itertools::Itertools;
 use pest::iterators::{Pair, Pairs};
 use pest::Parser;
 use pest_derive::Parser;
 
-use crate::commands::{Command, CommandParser};
+use crate::commands::{
+    Command, CommandParser, Condition, LoopSource, Number, Occurrence, Pattern, Position, ScriptHeader, SortOrder,
+    Substitution, Transform, ValueSelector,
+};
+use crate::commands::context::escape_references;
 use crate::commands::parser_exts::{InnerUnwrap, PairExt, PairsExt, SupportsQuoting};
-use crate::opts::parsed_args::OptName;
+use crate::opts::parsed_args::{MergePrecedence, OptName};
 
 #[derive(Parser)]
 #[grammar = "grammar/full.pest"]
 struct InternalParser;
 
 #[derive(Default)]
-pub struct FullCommandParser;
+pub struct FullCommandParser {
+    /// The path of the parsed script, which included scripts are relative to (instead of the working directory)
+    script_path: Option<PathBuf>,
+}
 
 impl CommandParser for FullCommandParser {
     fn parse_from_script_src<R: BufRead>(&self, mut script: R) -> Result<Vec<Command>> {
@@ -25,15 +35,15 @@ impl CommandParser for FullCommandParser {
     }
 
     fn parse_from_script(&self, script: &str) -> Result<Vec<Command>> {
-        InternalParser::parse(Rule::commands, script)
-                .wrap_err("Cannot parse full command script")?
-                .next().wrap_err("No commands found in input")?
-                .into_inner()
-                .map(parse_command)
-                .collect_commands()
+        let (header, commands) = self.parse_script(script)?;
+        if !header.is_empty() {
+            bail!("Header directives are only supported in scripts that are run with --run");
+        }
+        Ok(commands)
     }
 
-    fn parse_from_args<'a, I, S>(&self, commands: I) -> Result<Vec<Command>> where S: AsRef<str>, I: Iterator<Item=S> {
+    fn parse_from_args<I, S>(&self, commands: I) -> Result<Vec<Command>> where S: AsRef<str>, I: Iterator<Item=S> {
+        let mut expander = self.expander();
         commands
                 .map(|command| {
                     let command = InternalParser::parse(
@@ -43,12 +53,293 @@ impl CommandParser for FullCommandParser {
                                     matches.next().ok_or_else(|| eyre!("No command found in input: {}", command.as_ref()))
                             )?;
 
-                    parse_command(command)
+                    expander.parse_statement(command)
                 })
                 .collect_commands()
     }
 }
 
+impl FullCommandParser {
+    /// Creates a parser for the script at `path`, which is used for resolving the scripts it includes
+    pub fn for_script(path: impl Into<PathBuf>) -> Self {
+        FullCommandParser { script_path: Some(path.into()) }
+    }
+
+    /// Parses a script that may start with a header (see [ScriptHeader])
+    pub fn parse_script(&self, script: &str) -> Result<(ScriptHeader, Vec<Command>)> {
+        let statements = InternalParser::parse(Rule::commands, script)
+                .wrap_err("Cannot parse full command script")?
+                .next().wrap_err("No commands found in input")?
+                .into_inner();
+
+        let mut expander = self.expander();
+        let mut header = ScriptHeader::default();
+        let mut commands = Vec::new();
+        // Statements that expand to no commands (e.g. macro definitions) also end the header
+        let mut header_ended = false;
+        for statement in statements {
+            if statement.as_rule() == Rule::header {
+                if header_ended {
+                    bail!("Header directives must come before all commands: {}", statement.as_str());
+                }
+                parse_header(statement, &mut header)?;
+            } else if statement.as_rule() != Rule::EOI {
+                header_ended = true;
+                commands.extend(expander.parse_statement(statement)?);
+            }
+        }
+        Ok((header, commands))
+    }
+
+    fn expander(&self) -> Expander {
+        let script_path = self.script_path.as_deref();
+        Expander {
+            base_dir: script_path.and_then(Path::parent).map(Path::to_path_buf).unwrap_or_default(),
+            includes: script_path.into_iter().filter_map(|path| path.canonicalize().ok()).collect(),
+            macros: HashMap::new(),
+        }
+    }
+}
+
+/// A macro defined with `macro NAME(PARAMS) ... end`
+struct Macro {
+    params: Vec<String>,
+    body: Vec<Command>,
+}
+
+/// Expands includes and macros while parsing the statements of a script
+struct Expander {
+    /// The directory that included paths are relative to (the directory of the script being parsed)
+    base_dir: PathBuf,
+
+    /// The canonical paths of the scripts being parsed (outermost first), for detecting include cycles
+    includes: Vec<PathBuf>,
+
+    macros: HashMap<String, Macro>,
+}
+
+impl Expander {
+    fn parse_statement(&mut self, statement: Pair<Rule>) -> Result<Vec<Command>> {
+        // Statements that contain blocks may use macros, so they are parsed here (instead of in parse_command)
+        let rule = statement.as_rule();
+        let expanded_rules = [
+            Rule::include_command, Rule::macro_block, Rule::macro_call, Rule::if_block, Rule::for_block,
+        ];
+        if !expanded_rules.contains(&rule) {
+            return Ok(parse_command(statement)?.into_iter().collect());
+        }
+
+        let statement_str = statement.as_str();
+        let mut matches = statement.into_inner();
+        let commands = match rule {
+            Rule::include_command => {
+                let path = self.base_dir.join(matches.expect_rule(Rule::quoted)?.parse_quoted_into_string()?);
+                self.include(&path)
+                        .wrap_err_with(|| format!("Failed to include script: {statement_str}"))?
+            }
+            Rule::macro_block => {
+                let name = matches.expect_rule(Rule::macro_name)?.as_str().to_string();
+                let params = matches.expect_rule(Rule::macro_params)?.into_inner()
+                        .map(|param| param.as_str().to_string())
+                        .collect_vec();
+                let body = self.parse_block(matches.expect_rule(Rule::block)?)?;
+                if self.macros.insert(name.clone(), Macro { params, body }).is_some() {
+                    bail!("Macro is already defined: {name}");
+                }
+                vec![]
+            }
+            Rule::macro_call => {
+                let name = matches.expect_rule(Rule::macro_name)?.as_str();
+                let args: Vec<String> = matches.map(parse_call_arg).try_collect()?;
+                let definition = self.macros.get(name).wrap_err_with(|| format!("Unknown macro: {name}"))?;
+                if args.len() != definition.params.len() {
+                    bail!("Macro {name} expects {} arguments, but got {}: {statement_str}",
+                          definition.params.len(), args.len());
+                }
+
+                // Arguments are passed as variables, so that they are expanded like any other variable
+                let params = definition.params.iter().cloned().zip(args).collect();
+                vec![Command::Call { params, body: definition.body.clone() }]
+            }
+            Rule::if_block => {
+                let condition = parse_condition(matches.expect_rule(Rule::or_condition)?)?;
+                let then = self.parse_block(matches.expect_rule(Rule::block)?)?;
+                let otherwise = matches.attempt_rule(Rule::block)
+                        .map(|block| self.parse_block(block)).transpose()?.unwrap_or_default();
+                vec![Command::If { condition, then, otherwise }]
+            }
+            Rule::for_block => {
+                let variable = matches.expect_rule(Rule::env_name)?.as_str().to_string();
+                let source = match matches.expect_rules(&[Rule::loop_option, Rule::loop_positional])? {
+                    source if source.as_rule() == Rule::loop_option => {
+                        LoopSource::Option(source.into_inner().expect_option_name()?)
+                    }
+                    _ => LoopSource::Positional,
+                };
+                let body = self.parse_block(matches.expect_rule(Rule::block)?)?;
+                vec![Command::ForEach { variable, source, body }]
+            }
+            _ => bail!("Unknown statement rule: {rule:?}")
+        };
+        Ok(commands)
+    }
+
+    /// Parses the statements of a block (e.g. the body of an `if`), where header directives are not allowed
+    fn parse_block(&mut self, block: Pair<Rule>) -> Result<Vec<Command>> {
+        block.into_inner()
+                .map(|statement| match statement.as_rule() {
+                    Rule::header => bail!("Header directives are not allowed inside blocks: {}", statement.as_str()),
+                    Rule::macro_block => bail!("Macros cannot be defined inside blocks: {}", statement.as_str()),
+                    _ => self.parse_statement(statement),
+                })
+                .collect_commands()
+    }
+
+    /// Parses the commands of an included script, which can define macros but cannot have a header
+    fn include(&mut self, path: &Path) -> Result<Vec<Command>> {
+        let canonical_path = path.canonicalize()
+                .wrap_err_with(|| format!("Failed to find script file: {}", path.display()))?;
+        if self.includes.contains(&canonical_path) {
+            let cycle = self.includes.iter().chain([&canonical_path]).map(|path| path.display()).join(" -> ");
+            bail!("Scripts include each other in a cycle: {cycle}");
+        }
+        let script = fs::read_to_string(path)
+                .wrap_err_with(|| format!("Failed to read script file: {}", path.display()))?;
+        let statements = InternalParser::parse(Rule::commands, &script)
+                .wrap_err_with(|| format!("Cannot parse included script: {}", path.display()))?
+                .next().wrap_err("No commands found in input")?
+                .into_inner();
+
+        let base_dir = path.parent().map(Path::to_path_buf).unwrap_or_default();
+        let parent_base_dir = std::mem::replace(&mut self.base_dir, base_dir);
+        self.includes.push(canonical_path);
+        let result = statements
+                .map(|statement| match statement.as_rule() {
+                    Rule::header => bail!("Header directives are not allowed in included scripts: {}",
+                                          statement.as_str()),
+                    _ => self.parse_statement(statement),
+                })
+                .collect_commands();
+        self.includes.pop();
+        self.base_dir = parent_base_dir;
+        result
+    }
+}
+
+fn parse_header(directive: Pair<Rule>, header: &mut ScriptHeader) -> Result<()> {
+    let directive_str = directive.as_str();
+    let mut matches = directive.into_inner();
+    let name = matches.expect_rule(Rule::header_name)?.as_str();
+    let value = matches.expect_rules(&[Rule::quoted, Rule::raw_header_value])?;
+    let value = match value.as_rule() {
+        Rule::raw_header_value => value.as_str().trim_end().to_string(),
+        _ => value.parse_quoted_into_string()?,
+    };
+    header.add_directive(name, value)
+            .wrap_err_with(|| format!("Bad header directive: {directive_str}"))
+}
+
+fn parse_condition(condition: Pair<Rule>) -> Result<Condition> {
+    let rule = condition.as_rule();
+    let mut matches = condition.into_inner();
+
+    let condition = match rule {
+        Rule::or_condition | Rule::and_condition => {
+            let mut conditions: Vec<Condition> = matches.map(parse_condition).try_collect()?;
+            match (conditions.len(), rule) {
+                (1, _) => conditions.remove(0),
+                (_, Rule::or_condition) => Condition::Or(conditions),
+                _ => Condition::And(conditions),
+            }
+        }
+        Rule::not_condition => {
+            Condition::Not(Box::new(parse_condition(matches.next().wrap_err("Missing negated condition")?)?))
+        }
+        Rule::has_condition => Condition::Has(matches.expect_option_name()?),
+        Rule::matches_condition => {
+            Condition::Matches(matches.expect_option_name()?, parse_pattern(matches.expect_rule(Rule::name_regex)?)?)
+        }
+        Rule::positional_count_condition => {
+            let mut range = matches.expect_rule(Rule::count_range)?.into_inner();
+            let min = range.attempt_rule(Rule::range_min).map(|min| parse_count(min)).transpose()?;
+            let is_range = range.attempt_rule(Rule::range_dots).is_some();
+            let max = range.attempt_rule(Rule::range_max).map(|max| parse_count(max)).transpose()?;
+            Condition::PositionalCount {
+                min: min.unwrap_or(0),
+                max: if is_range { max } else { min },
+            }
+        }
+        Rule::equals_condition => {
+            let option = matches.expect_option_name()?;
+            let operator = matches.expect_rules(&[Rule::equals_op, Rule::not_equals_op])?;
+            let negated = operator.as_rule() == Rule::not_equals_op;
+            let value = matches.read_values()?.into_iter().exactly_one()
+                    .map_err(|_| eyre!("Expected a single value to compare with"))?;
+            let equals = Condition::Equals(option, value);
+            if negated { Condition::Not(Box::new(equals)) } else { equals }
+        }
+        _ => bail!("Unknown condition rule: {rule:?}")
+    };
+
+    Ok(condition)
+}
+
+fn parse_pattern(name_regex: Pair<Rule>) -> Result<Pattern> {
+    Pattern::from_regex(name_regex.into_inner().expect_rule(Rule::regex_pattern)?.as_str())
+}
+
+fn parse_position(position: Pair<Rule>) -> Result<Position> {
+    let position = position.into_inner().next().wrap_err("Missing position")?;
+    let position = match position.as_rule() {
+        Rule::position_first => Position::First,
+        Rule::position_last => Position::Last,
+        Rule::position_before => Position::Before(position.into_inner().expect_option_name()?),
+        Rule::position_after => Position::After(position.into_inner().expect_option_name()?),
+        rule => bail!("Unknown position rule: {rule:?}"),
+    };
+    Ok(position)
+}
+
+fn parse_transform(transform: Pair<Rule>) -> Result<Transform> {
+    let transform = match transform.as_rule() {
+        Rule::transform_dirname => Transform::Dirname,
+        Rule::transform_basename => Transform::Basename,
+        Rule::transform_strip_suffix => {
+            Transform::StripSuffix(parse_call_arg(transform.into_inner().expect_rule(Rule::call_arg)?)?)
+        }
+        Rule::transform_lower => Transform::Lower,
+        Rule::transform_upper => Transform::Upper,
+        Rule::transform_trim => Transform::Trim,
+        Rule::transform_normalize_path => Transform::NormalizePath {
+            relative_to: transform.into_inner().attempt_rule(Rule::call_arg).map(parse_call_arg).transpose()?,
+        },
+        rule => bail!("Unknown transform rule: {rule:?}"),
+    };
+    Ok(transform)
+}
+
+/// Parses a number, which may be wrapped (e.g. in a range bound)
+fn parse_number(number: Pair<Rule>) -> Result<Number> {
+    Number::parse(number.as_str())
+}
+
+fn parse_count(count: Pair<Rule>) -> Result<usize> {
+    count.as_str().parse().wrap_err_with(|| format!("Invalid count: {}", count.as_str()))
+}
+
+fn parse_index(pos_index: Pair<Rule>) -> Result<isize> {
+    pos_index.as_str().parse().wrap_err_with(|| format!("Invalid index: {}", pos_index.as_str()))
+}
+
+fn parse_substitution(substitution: Pair<Rule>) -> Result<Substitution> {
+    let mut matches = substitution.into_inner();
+    Substitution::new(
+        matches.expect_rule(Rule::regex_pattern)?.as_str(),
+        matches.expect_rule(Rule::regex_replacement)?.as_str(),
+        matches.expect_rule(Rule::regex_flags)?.as_str(),
+    )
+}
+
 fn parse_command(command: Pair<Rule>) -> Result<Option<Command>> {
     let rule = command.as_rule();
     let mut matches = command.into_inner();
@@ -66,6 +357,26 @@ fn parse_command(command: Pair<Rule>) -> Result<Option<Command>> {
                 values: matches.read_values()?,
             }
         }
+        Rule::insert_command => {
+            Command::Insert {
+                position: parse_position(matches.expect_rule(Rule::position)?)?,
+                option: matches.expect_option_name()?,
+                values: matches.read_values()?,
+            }
+        }
+        Rule::move_command => {
+            Command::Move {
+                option: matches.expect_option_name()?,
+                position: parse_position(matches.expect_rule(Rule::position)?)?,
+            }
+        }
+        Rule::sort_command => {
+            let order = match matches.attempt_rule(Rule::sort_by_vocabulary) {
+                Some(_) => SortOrder::Vocabulary,
+                None => SortOrder::Name,
+            };
+            Command::Sort { order }
+        }
         Rule::radd_command => {
             Command::RepeatedAdd {
                 option: matches.expect_option_name()?,
@@ -73,7 +384,30 @@ fn parse_command(command: Pair<Rule>) -> Result<Option<Command>> {
             }
         }
         Rule::remove_command => {
-            Command::Remove { option: matches.expect_option_name()? }
+            match matches.attempt_rule(Rule::name_regex) {
+                Some(regex) => Command::RemoveMatching { pattern: parse_pattern(regex)? },
+                None => Command::Remove { option: matches.expect_option_name()? },
+            }
+        }
+        Rule::remove_matching_command => {
+            let pattern = matches.expect_rules(&[Rule::name_regex, Rule::quoted])?;
+            let pattern = match pattern.as_rule() {
+                Rule::name_regex => parse_pattern(pattern)?,
+                _ => Pattern::from_glob(&pattern.parse_quoted_into_string()?)?,
+            };
+            Command::RemoveMatching { pattern }
+        }
+        Rule::default_command => {
+            Command::Default {
+                option: matches.expect_option_name()?,
+                values: matches.read_values()?,
+            }
+        }
+        Rule::rename_command => {
+            let option = matches.expect_option_name()?;
+            let new_name = matches.expect_option_name()?;
+            let values = matches.read_values()?;
+            Command::Rename { option, new_name, values: (!values.is_empty()).then_some(values) }
         }
         Rule::append_command => {
             Command::Append {
@@ -85,6 +419,179 @@ fn parse_command(command: Pair<Rule>) -> Result<Option<Command>> {
                 items: matches.read_values()?,
             }
         }
+        Rule::prepend_command => {
+            Command::Prepend {
+                delimiter: matches
+                        .attempt_rule(Rule::quoted)
+                        .map(|pair| pair.parse_quoted_into_string())
+                        .unwrap_or(Ok("".to_string()))?,
+                option: matches.expect_option_name()?,
+                items: matches.read_values()?,
+            }
+        }
+        Rule::unappend_command => {
+            let drop_empty = matches.attempt_rule(Rule::drop_empty).is_some();
+            let delimiter = matches.expect_rule(Rule::quoted)?.parse_quoted_into_string()?;
+            let option = matches.expect_option_name()?;
+            let items = match matches.attempt_rule(Rule::name_regex) {
+                Some(regex) => ValueSelector::Matching(parse_pattern(regex)?),
+                None => ValueSelector::Exact(matches.read_values()?),
+            };
+            Command::Unappend { option, delimiter, items, drop_empty }
+        }
+        Rule::clamp_command => {
+            Command::Clamp {
+                option: matches.expect_option_name()?,
+                min: matches.attempt_rule(Rule::clamp_min).map(parse_number).transpose()?,
+                max: matches.attempt_rule(Rule::clamp_max).map(parse_number).transpose()?,
+            }
+        }
+        Rule::max_command => {
+            Command::Clamp { option: matches.expect_option_name()?, min: Some(matches.expect_number()?), max: None }
+        }
+        Rule::min_command => {
+            Command::Clamp { option: matches.expect_option_name()?, min: None, max: Some(matches.expect_number()?) }
+        }
+        Rule::add_to_command => {
+            Command::AddTo { option: matches.expect_option_name()?, amount: matches.expect_number()? }
+        }
+        Rule::exclusive_command => {
+            Command::Exclusive { options: matches.map(parse_option_name).try_collect()? }
+        }
+        Rule::check_exclusive_command => Command::CheckExclusive,
+        Rule::copy_command => {
+            Command::Copy { from: matches.expect_option_name()?, to: matches.expect_option_name()? }
+        }
+        Rule::derive_command => {
+            let option = matches.expect_option_name()?;
+            let add = matches.attempt_rule(Rule::derive_add_op).is_some();
+            let template = matches.expect_value()?;
+            let transforms = matches.map(parse_transform).try_collect()?;
+            Command::Derive { option, template, transforms, add }
+        }
+        Rule::kv_set_command => {
+            let delimiter = matches.attempt_quoted()?;
+            let option = matches.expect_option_name()?;
+            let entry = matches.read_values()?.into_iter().exactly_one()
+                    .map_err(|_| eyre!("Expected a single KEY=VALUE entry"))?;
+            let (key, value) = match entry.split_once('=') {
+                Some((key, value)) => (key.to_string(), Some(value.to_string())),
+                None => (entry, None),
+            };
+            if key.is_empty() {
+                bail!("The key of an entry cannot be empty");
+            }
+            Command::KeySet { option, delimiter, key, value }
+        }
+        Rule::kv_remove_command => {
+            Command::KeyRemove {
+                delimiter: matches.attempt_quoted()?,
+                option: matches.expect_option_name()?,
+                keys: matches.read_values()?,
+            }
+        }
+        Rule::replace_command => {
+            Command::Replace {
+                option: matches.expect_option_name()?,
+                substitution: parse_substitution(matches.expect_rule(Rule::substitution)?)?,
+            }
+        }
+        Rule::pos_replace_command => {
+            Command::ReplacePositional { substitution: parse_substitution(matches.expect_rule(Rule::substitution)?)? }
+        }
+        Rule::normalize_path_command => {
+            let option = matches.expect_option_name()?;
+            Command::TransformValues { option, transform: matches.read_normalize_path()? }
+        }
+        Rule::lower_command => {
+            Command::TransformValues { option: matches.expect_option_name()?, transform: Transform::Lower }
+        }
+        Rule::trim_command => {
+            Command::TransformValues { option: matches.expect_option_name()?, transform: Transform::Trim }
+        }
+        Rule::pos_normalize_path_command => Command::TransformPositional { transform: matches.read_normalize_path()? },
+        Rule::pos_lower_command => Command::TransformPositional { transform: Transform::Lower },
+        Rule::pos_trim_command => Command::TransformPositional { transform: Transform::Trim },
+        Rule::pos_prepend_command => {
+            Command::InsertPositional { index: 0, values: matches.read_values()? }
+        }
+        Rule::pos_append_command => {
+            Command::AppendPositional { values: matches.read_values()? }
+        }
+        Rule::pos_insert_command => {
+            Command::InsertPositional {
+                index: parse_index(matches.expect_rule(Rule::pos_index)?)?,
+                values: matches.read_values()?,
+            }
+        }
+        Rule::pos_remove_command => {
+            match matches.attempt_rules(&[Rule::name_regex, Rule::pos_index]) {
+                Some(regex) if regex.as_rule() == Rule::name_regex => {
+                    Command::RemovePositionalMatching { pattern: parse_pattern(regex)? }
+                }
+                Some(index) => Command::RemovePositional { index: parse_index(index)? },
+                None => Command::RemovePositionalValues { values: matches.read_values()? },
+            }
+        }
+        Rule::pos_clear_command => Command::ClearPositional,
+        Rule::dedupe_command => {
+            Command::Dedupe { keep: matches.read_occurrence(), option: matches.attempt_option_name()? }
+        }
+        Rule::dedupe_values_command => {
+            Command::DedupeValues {
+                keep: matches.read_occurrence(),
+                option: matches.expect_option_name()?,
+                delimiter: matches.expect_rule(Rule::quoted)?.parse_quoted_into_string()?,
+            }
+        }
+        Rule::explode_command => {
+            Command::Explode { option: matches.expect_option_name()?, delimiter: matches.attempt_quoted()? }
+        }
+        Rule::implode_command => {
+            Command::Implode { option: matches.expect_option_name()?, delimiter: matches.attempt_quoted()? }
+        }
+        Rule::let_command => {
+            Command::Let {
+                name: matches.expect_rule(Rule::env_name)?.as_str().to_string(),
+                value: matches.read_values()?.into_iter().exactly_one()
+                        .map_err(|_| eyre!("Expected a single value for the variable"))?,
+            }
+        }
+        Rule::merge_env_command => {
+            let precedence = match matches.attempt_rules(&[Rule::keep_precedence, Rule::override_precedence]) {
+                Some(pair) if pair.as_rule() == Rule::override_precedence => MergePrecedence::Merged,
+                _ => MergePrecedence::Existing,
+            };
+            Command::MergeEnv {
+                variable: matches.expect_rule(Rule::env_name)?.as_str().to_string(),
+                precedence,
+            }
+        }
+        Rule::assert_command => {
+            let condition = matches.expect_rule(Rule::or_condition)?;
+            // Default messages are not interpolated, since they quote the script
+            let default_message = escape_references(&format!("Assertion failed: {}", condition.as_str()));
+            Command::Assert {
+                condition: parse_condition(condition)?,
+                message: matches.attempt_quoted()?.unwrap_or(default_message),
+            }
+        }
+        Rule::require_command => {
+            let option = matches.expect_option_name()?;
+            let default_message = || escape_references(&format!("Missing required option: {option}"));
+            Command::Assert {
+                message: matches.attempt_quoted()?.unwrap_or_else(default_message),
+                condition: Condition::Has(option),
+            }
+        }
+        Rule::forbid_command => {
+            let option = matches.expect_option_name()?;
+            let default_message = || escape_references(&format!("Option is not allowed: {option}"));
+            Command::Assert {
+                message: matches.attempt_quoted()?.unwrap_or_else(default_message),
+                condition: Condition::Not(Box::new(Condition::Has(option))),
+            }
+        }
         Rule::EOI => return Ok(None), // Ignore EOI
         _ => bail!("Unknown command rule: {rule:?}")
     };
@@ -97,36 +604,87 @@ trait CommandsIterator
     fn collect_commands(&mut self) -> Result<Vec<Command>>;
 }
 
-impl<I: Iterator<Item=Result<Option<Command>>>> CommandsIterator for I {
+impl<I: Iterator<Item=Result<Vec<Command>>>> CommandsIterator for I {
     fn collect_commands(&mut self) -> Result<Vec<Command>> {
         self
-                .filter_map(|result| result.transpose())
+                .flatten_ok()
                 .try_collect().wrap_err("Failed to parse full command script")
     }
 }
 
+fn parse_value(value_arg: Pair<Rule>) -> Result<String> {
+    value_arg.map_single_wrapped().wrap_err("Expected quotable value")?.parse_quoted_into_string()
+}
+
+fn parse_call_arg(arg: Pair<Rule>) -> Result<String> {
+    match arg.clone().map_single_wrapped() {
+        Some(quoted) if quoted.as_rule() == Rule::quoted => quoted.parse_quoted_into_string(),
+        _ => Ok(arg.as_str().to_string()),
+    }
+}
+
+/// Parses an option name, where a `--` prefix makes it long even if it has a single character
+fn parse_option_name(name: Pair<Rule>) -> Result<OptName> {
+    let text = name.as_str();
+    let mut parts = name.into_inner();
+    let prefix = parts.attempt_rule(Rule::long_prefix).map_or("", |prefix| prefix.as_str());
+    let name = match parts.attempt_rule(Rule::quoted) {
+        Some(quoted) => quoted.parse_quoted()?,
+        None => text[prefix.len()..].into(),
+    };
+    format!("{prefix}{name}").parse()
+}
+
 trait ParserPairsExt {
     fn expect_option_name(&mut self) -> Result<OptName>;
+    fn attempt_option_name(&mut self) -> Result<Option<OptName>>;
     fn read_values(&mut self) -> Result<Vec<String>>;
+    fn expect_value(&mut self) -> Result<String>;
+    fn expect_number(&mut self) -> Result<Number>;
+    fn read_occurrence(&mut self) -> Occurrence;
+    fn attempt_quoted(&mut self) -> Result<Option<String>>;
+    fn read_normalize_path(&mut self) -> Result<Transform>;
 }
 
 impl<'i> ParserPairsExt for Pairs<'i, Rule> {
     fn expect_option_name(&mut self) -> Result<OptName> {
-        self
-                .expect_rule(Rule::name)?
-                .parse_quoted()?
-                .try_into()
+        parse_option_name(self.expect_rule(Rule::name)?)
+    }
+
+    fn attempt_option_name(&mut self) -> Result<Option<OptName>> {
+        self.attempt_rule(Rule::name).map(parse_option_name).transpose()
+    }
+
+    fn attempt_quoted(&mut self) -> Result<Option<String>> {
+        self.attempt_rule(Rule::quoted).map(|message| message.parse_quoted_into_string()).transpose()
+    }
+
+    fn read_normalize_path(&mut self) -> Result<Transform> {
+        let relative_to = self.attempt_rule(Rule::relative_to).map(|dir| dir.into_inner().expect_value()).transpose()?;
+        Ok(Transform::NormalizePath { relative_to })
+    }
+
+    fn read_occurrence(&mut self) -> Occurrence {
+        match self.attempt_rules(&[Rule::keep_first, Rule::keep_last]) {
+            Some(pair) if pair.as_rule() == Rule::keep_last => Occurrence::Last,
+            _ => Occurrence::First,
+        }
     }
 
     fn read_values(&mut self) -> Result<Vec<String>> {
         self
                 .filter(|pair| pair.as_rule() == Rule::value_arg)
-                .map(|pair| pair
-                        .map_single_wrapped()
-                        .wrap_err("Expected quotable value")?
-                        .parse_quoted_into_string())
+                .map(parse_value)
                 .try_collect()
     }
+
+    fn expect_value(&mut self) -> Result<String> {
+        parse_value(self.expect_rule(Rule::value_arg)?)
+    }
+
+    fn expect_number(&mut self) -> Result<Number> {
+        parse_number(self.expect_rule(Rule::number)?)
+    }
 }
 
 impl SupportsQuoting for Rule {
@@ -139,12 +697,29 @@ impl SupportsQuoting for Rule {
 #[cfg(test)]
 mod test {
     use paste::paste;
-    use crate::commands::{Command, CommandParser, FullCommandParser};
-    use crate::commands::Command::{Append, RepeatedAdd, Set};
+    use crate::commands::{
+        Command, CommandParser, Condition, FullCommandParser, LoopSource, Number, Occurrence, Pattern, Position,
+        SortOrder, Substitution, Transform, ValueSelector,
+    };
+    use crate::commands::Command::{
+        Add, Append, Assert, CheckExclusive, Default, Exclusive, Explode, If, Implode, MergeEnv, Remove, RemoveMatching,
+        Rename, RepeatedAdd, Replace, ReplacePositional, Set,
+    };
+    use crate::opts::parsed_args::MergePrecedence;
+    use crate::cli::Dialect;
+    use crate::commands::ScriptHeader;
+    use crate::opts::grammar::Gnu;
+    use crate::opts::grammar::gnu::PositionalArgumentsMode;
+    use crate::opts::vocabulary::Vocabulary;
+    use crate::util::testing::{assert_err, assert_err_contains};
     use crate::util::testing::opts::sv;
     use crate::util::testing::opts::name::{short, long};
 
-    static PARSER: FullCommandParser = FullCommandParser {};
+    fn number(text: &str) -> Number {
+        Number::parse(text).unwrap()
+    }
+
+    static PARSER: FullCommandParser = FullCommandParser { script_path: None };
 
     fn test_parse_args(input_cmds: &[&str], expected_result: &[Command]) -> eyre::Result<()> {
         let result = PARSER.parse_from_args(input_cmds.iter())?;
@@ -260,4 +835,518 @@ mod test {
         Set { option: short!('b'), values: sv![] },
         Set { option: long!("long"), values: sv!["abc", "def"] }
     );
-}
\ No newline at end of file
+
+    test_cmds!(single_char_long_names, "set --O=3", "remove --'v'", "set --long" =>
+        Set { option: long!("O"), values: sv!["3"] },
+        Remove { option: long!("v") },
+        Set { option: long!("long"), values: sv![] }
+    );
+
+    test_cmds!(ordering, "insert first L=/opt/lib", "insert before 'x' std=c11", "insert after I v",
+               "insert last a = 1 2", "move L first", "move x after 'I'", "move v last", "sort", "sort vocabulary" =>
+        Command::Insert { option: short!('L'), values: sv!["/opt/lib"], position: Position::First },
+        Command::Insert { option: long!("std"), values: sv!["c11"], position: Position::Before(short!('x')) },
+        Command::Insert { option: short!('v'), values: sv![], position: Position::After(short!('I')) },
+        Command::Insert { option: short!('a'), values: sv!["1", "2"], position: Position::Last },
+        Command::Move { option: short!('L'), position: Position::First },
+        Command::Move { option: short!('x'), position: Position::After(short!('I')) },
+        Command::Move { option: short!('v'), position: Position::Last },
+        Command::Sort { order: SortOrder::Name },
+        Command::Sort { order: SortOrder::Vocabulary }
+    );
+
+    #[test]
+    fn parse_bad_ordering() {
+        assert_err!(PARSER.parse_from_args(["insert L=x"].iter()));
+        assert_err!(PARSER.parse_from_args(["insert firstly L=x"].iter()));
+        assert_err!(PARSER.parse_from_args(["move L"].iter()));
+        assert_err!(PARSER.parse_from_script("sort names"));
+        assert_err!(PARSER.parse_from_script("sorted"));
+    }
+
+    test_cmds!(key_values, "kv.set o ServerAliveInterval=30", "kv.set ',' 'o' ro", "kv.set D 'name=a b'",
+               "kv.remove e DEBUG TRACE", "kv.remove ',' o rw" =>
+        Command::KeySet {
+            option: short!('o'),
+            delimiter: None,
+            key: "ServerAliveInterval".to_string(),
+            value: Some("30".to_string()),
+        },
+        Command::KeySet { option: short!('o'), delimiter: Some(",".to_string()), key: "ro".to_string(), value: None },
+        Command::KeySet {
+            option: short!('D'),
+            delimiter: None,
+            key: "name".to_string(),
+            value: Some("a b".to_string()),
+        },
+        Command::KeyRemove { option: short!('e'), delimiter: None, keys: sv!["DEBUG", "TRACE"] },
+        Command::KeyRemove { option: short!('o'), delimiter: Some(",".to_string()), keys: sv!["rw"] }
+    );
+
+    #[test]
+    fn parse_bad_key_values() {
+        assert_err!(PARSER.parse_from_args(["kv.set o"].iter()));
+        assert_err!(PARSER.parse_from_args(["kv.remove o"].iter()));
+        assert_err_contains!(PARSER.parse_from_args(["kv.set o '=x'"].iter()).map_err(|e| e.root_cause().to_string()),
+                             "key of an entry cannot be empty");
+    }
+
+    test_cmds!(copy_and_derive, "copy target 'target-dir'", "derive cache-dir = ${opt:output} | dirname",
+               "derive C += 'target=${opt:target}'",
+               "derive name=${opt:o}|basename | strip-suffix(.tar.gz)|upper | strip-suffix( ' (1)' ) | lower" =>
+        Command::Copy { from: long!("target"), to: long!("target-dir") },
+        Command::Derive {
+            option: long!("cache-dir"),
+            template: "${opt:output}".to_string(),
+            transforms: vec![Transform::Dirname],
+            add: false,
+        },
+        Command::Derive {
+            option: short!('C'),
+            template: "target=${opt:target}".to_string(),
+            transforms: vec![],
+            add: true,
+        },
+        Command::Derive {
+            option: long!("name"),
+            template: "${opt:o}".to_string(),
+            transforms: vec![
+                Transform::Basename, Transform::StripSuffix(".tar.gz".to_string()), Transform::Upper,
+                Transform::StripSuffix(" (1)".to_string()), Transform::Lower,
+            ],
+            add: false,
+        }
+    );
+
+    #[test]
+    fn parse_bad_derive() {
+        assert_err!(PARSER.parse_from_script("copy target"));
+        assert_err!(PARSER.parse_from_script("derive a = b | dirnames"));
+        assert_err!(PARSER.parse_from_script("derive a = b | reverse"));
+        assert_err!(PARSER.parse_from_script("derive a = b c"));
+    }
+
+    test_cmds!(value_transforms, "normalize-path I", "normalize-path output relative-to '/work dir'", "lower color",
+               "trim 'name'", "pos.normalize-path relative-to ${PWD}", "pos.lower", "pos.trim",
+               "derive dir = ${opt:o} | trim | normalize-path | normalize-path(/work)" =>
+        Command::TransformValues { option: short!('I'), transform: Transform::NormalizePath { relative_to: None } },
+        Command::TransformValues {
+            option: long!("output"),
+            transform: Transform::NormalizePath { relative_to: Some("/work dir".to_string()) },
+        },
+        Command::TransformValues { option: long!("color"), transform: Transform::Lower },
+        Command::TransformValues { option: long!("name"), transform: Transform::Trim },
+        Command::TransformPositional {
+            transform: Transform::NormalizePath { relative_to: Some("${PWD}".to_string()) },
+        },
+        Command::TransformPositional { transform: Transform::Lower },
+        Command::TransformPositional { transform: Transform::Trim },
+        Command::Derive {
+            option: long!("dir"),
+            template: "${opt:o}".to_string(),
+            transforms: vec![
+                Transform::Trim,
+                Transform::NormalizePath { relative_to: None },
+                Transform::NormalizePath { relative_to: Some("/work".to_string()) },
+            ],
+            add: false,
+        }
+    );
+
+    #[test]
+    fn parse_bad_value_transforms() {
+        assert_err!(PARSER.parse_from_script("lower"));
+        assert_err!(PARSER.parse_from_script("normalize-path I relative-to"));
+        assert_err!(PARSER.parse_from_script("pos.trim I"));
+    }
+
+    test_cmds!(numbers, "clamp j 1..8", "clamp 'max-memory' 512M..", "clamp timeout ..1.5h", "max timeout=30",
+               "min j = 8", "add-to retries=-1" =>
+        Command::Clamp { option: short!('j'), min: Some(number("1")), max: Some(number("8")) },
+        Command::Clamp { option: long!("max-memory"), min: Some(number("512M")), max: None },
+        Command::Clamp { option: long!("timeout"), min: None, max: Some(number("1.5h")) },
+        Command::Clamp { option: long!("timeout"), min: Some(number("30")), max: None },
+        Command::Clamp { option: short!('j'), min: None, max: Some(number("8")) },
+        Command::AddTo { option: long!("retries"), amount: number("-1") }
+    );
+
+    #[test]
+    fn parse_bad_numbers() {
+        assert_err!(PARSER.parse_from_script("clamp j 1"));
+        assert_err!(PARSER.parse_from_script("clamp j .."));
+        assert_err!(PARSER.parse_from_script("max j=x"));
+        assert_err_contains!(PARSER.parse_from_script("max j=8x").map_err(|e| e.root_cause().to_string()),
+                             "Unknown unit");
+    }
+
+    test_cmds!(merge_env, "merge-env JAVA_TOOL_OPTIONS", "merge-env keep GREP_OPTIONS", "merge-env override _X1" =>
+        MergeEnv { variable: "JAVA_TOOL_OPTIONS".to_string(), precedence: MergePrecedence::Existing },
+        MergeEnv { variable: "GREP_OPTIONS".to_string(), precedence: MergePrecedence::Existing },
+        MergeEnv { variable: "_X1".to_string(), precedence: MergePrecedence::Merged }
+    );
+
+    test_cmds!(rename, "rename verbose log-level=debug", "rename v quiet", "rename a b = 1 2" =>
+        Rename { option: long!("verbose"), new_name: long!("log-level"), values: Some(sv!["debug"]) },
+        Rename { option: short!('v'), new_name: long!("quiet"), values: None },
+        Rename { option: short!('a'), new_name: short!('b'), values: Some(sv!["1", "2"]) }
+    );
+
+    test_cmds!(default, "default color=auto", "default v", "default a = 1 2" =>
+        Default { option: long!("color"), values: sv!["auto"] },
+        Default { option: short!('v'), values: sv![] },
+        Default { option: short!('a'), values: sv!["1", "2"] }
+    );
+
+    test_script!(if_blocks, r###"
+        if has ci  # Comment
+            add no-cache
+        end
+        if not has v and color == always or (a != '1 2')
+            remove color
+        else
+          # Nested blocks
+            if has ci
+            end
+        end # Trailing comment
+        "### =>
+        If {
+            condition: Condition::Has(long!("ci")),
+            then: vec![Add { option: long!("no-cache"), values: sv![] }],
+            otherwise: vec![],
+        },
+        If {
+            condition: Condition::Or(vec![
+                Condition::And(vec![
+                    Condition::Not(Box::new(Condition::Has(short!('v')))),
+                    Condition::Equals(long!("color"), "always".to_string()),
+                ]),
+                Condition::Not(Box::new(Condition::Equals(short!('a'), "1 2".to_string()))),
+            ]),
+            then: vec![Remove { option: long!("color") }],
+            otherwise: vec![If { condition: Condition::Has(long!("ci")), then: vec![], otherwise: vec![] }],
+        }
+    );
+
+    test_cmds!(assertions, "require o", "forbid 'no-cache' 'Caching is required'", "assert positional count 1..3",
+               "assert I matches /^\\// 'Include paths must be absolute'", "assert positional count ..2 and not has v",
+               "assert positional count 2" =>
+        Assert { condition: Condition::Has(short!('o')), message: "Missing required option: o".to_string() },
+        Assert {
+            condition: Condition::Not(Box::new(Condition::Has(long!("no-cache")))),
+            message: "Caching is required".to_string(),
+        },
+        Assert {
+            condition: Condition::PositionalCount { min: 1, max: Some(3) },
+            message: "Assertion failed: positional count 1..3".to_string(),
+        },
+        Assert {
+            condition: Condition::Matches(short!('I'), Pattern::from_regex("^/").unwrap()),
+            message: "Include paths must be absolute".to_string(),
+        },
+        Assert {
+            condition: Condition::And(vec![
+                Condition::PositionalCount { min: 0, max: Some(2) },
+                Condition::Not(Box::new(Condition::Has(short!('v')))),
+            ]),
+            message: "Assertion failed: positional count ..2 and not has v".to_string(),
+        },
+        Assert {
+            condition: Condition::PositionalCount { min: 2, max: Some(2) },
+            message: "Assertion failed: positional count 2".to_string(),
+        }
+    );
+
+    // Default messages quote the script, so its references are escaped rather than interpolated
+    test_cmds!(assertion_default_messages, "assert o == '${out}'", "require '${name}'" =>
+        Assert {
+            condition: Condition::Equals(short!('o'), "${out}".to_string()),
+            message: "Assertion failed: o == '$${out}'".to_string(),
+        },
+        Assert { condition: Condition::Has(long!("${name}")), message: "Missing required option: $${name}".to_string() }
+    );
+
+    #[test]
+    fn parse_bad_assertions() {
+        assert_err!(PARSER.parse_from_args(["require"].iter()));
+        assert_err!(PARSER.parse_from_args(["assert positional count"].iter()));
+        assert_err!(PARSER.parse_from_args(["assert I matches x"].iter()));
+    }
+
+    test_cmds!(explode_and_implode, "explode features", "explode I ':'", "implode 'files'", "implode features ','" =>
+        Explode { option: long!("features"), delimiter: None },
+        Explode { option: short!('I'), delimiter: Some(":".to_string()) },
+        Implode { option: long!("files"), delimiter: None },
+        Implode { option: long!("features"), delimiter: Some(",".to_string()) }
+    );
+
+    #[test]
+    fn parse_bad_explode_and_implode() {
+        assert_err!(PARSER.parse_from_script("explode"));
+        assert_err!(PARSER.parse_from_script("implode features ,"));
+    }
+
+    test_cmds!(exclusive, "exclusive quiet 'verbose' v", "check-exclusive" =>
+        Exclusive { options: vec![long!("quiet"), long!("verbose"), short!('v')] },
+        CheckExclusive
+    );
+
+    #[test]
+    fn parse_bad_exclusive() {
+        assert_err!(PARSER.parse_from_script("exclusive quiet"));
+        assert_err!(PARSER.parse_from_script("exclusive"));
+    }
+
+    test_script!(macros, r###"
+        macro harden(level, extra)  # Comment
+            set stack-protector=${level}
+            if has ci
+                add D=${extra}
+            end
+        end
+        macro verbose()
+            add v
+        end
+        harden(strong, '_FORTIFY_SOURCE=2')
+        if has g
+            verbose( )
+        end
+        "### =>
+        Command::Call {
+            params: vec![
+                ("level".to_string(), "strong".to_string()),
+                ("extra".to_string(), "_FORTIFY_SOURCE=2".to_string()),
+            ],
+            body: vec![
+                Set { option: long!("stack-protector"), values: sv!["${level}"] },
+                If {
+                    condition: Condition::Has(long!("ci")),
+                    then: vec![Add { option: short!('D'), values: sv!["${extra}"] }],
+                    otherwise: vec![],
+                },
+            ],
+        },
+        If {
+            condition: Condition::Has(short!('g')),
+            then: vec![Command::Call { params: vec![], body: vec![Add { option: short!('v'), values: sv![] }] }],
+            otherwise: vec![],
+        }
+    );
+
+    #[test]
+    fn parse_bad_macros() {
+        let root_cause = |script: &str| PARSER.parse_from_script(script).map_err(|e| e.root_cause().to_string());
+        assert_err_contains!(root_cause("undefined()"), "Unknown macro: undefined");
+        assert_err_contains!(root_cause("macro m(a)
+end
+m()"), "expects 1 arguments, but got 0");
+        assert_err_contains!(root_cause("macro m()
+end
+macro m()
+end"), "already defined");
+        assert_err_contains!(root_cause("macro m()
+  m()
+end"), "Unknown macro: m");
+        assert_err_contains!(root_cause("if has a
+macro m()
+end
+end"), "cannot be defined inside blocks");
+    }
+
+    #[test]
+    fn parse_includes() -> eyre::Result<()> {
+        let dir = std::env::temp_dir().join(format!("oco-include-test-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("shared"))?;
+        std::fs::write(dir.join("shared/hardening.oco"), "include 'flags.oco'\nmacro pie()\n  add pie\nend\n")?;
+        std::fs::write(dir.join("shared/flags.oco"), "add 'stack-protector'\n")?;
+        std::fs::write(dir.join("a.oco"), "include \"b.oco\"")?;
+        std::fs::write(dir.join("b.oco"), "include \"a.oco\"")?;
+        std::fs::write(dir.join("header.oco"), "@target ls")?;
+        std::fs::write(dir.join("empty.oco"), "# Nothing to do\n")?;
+
+        let parser = FullCommandParser::for_script(dir.join("main.oco"));
+        assert_eq!(parser.parse_from_script("include 'shared/hardening.oco'\npie()")?, vec![
+            Add { option: long!("stack-protector"), values: sv![] },
+            Command::Call { params: vec![], body: vec![Add { option: long!("pie"), values: sv![] }] },
+        ]);
+        assert_eq!(parser.parse_from_args(["include 'shared/hardening.oco'", "pie()"].iter())?.len(), 2);
+
+        let root_cause = |script: &str| parser.parse_from_script(script).map_err(|e| e.root_cause().to_string());
+        assert_err_contains!(root_cause("include 'a.oco'"), "include each other in a cycle");
+        assert_err_contains!(root_cause("include 'header.oco'"), "not allowed in included scripts");
+        assert_err_contains!(parser.parse_script("include 'empty.oco'\n@target ls"), "must come before all commands");
+        assert_err_contains!(root_cause("include 'missing.oco'"), "No such file");
+
+        std::fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    test_script!(for_loops, r###"
+        for dir in opt:I  # Comment
+            add isystem=${dir}
+        end
+        for f in positional
+            for d in opt:'define'
+                if has v
+                    add log=${f}:${d}
+                end
+            end
+        end
+        "### =>
+        Command::ForEach {
+            variable: "dir".to_string(),
+            source: LoopSource::Option(short!('I')),
+            body: vec![Add { option: long!("isystem"), values: sv!["${dir}"] }],
+        },
+        Command::ForEach {
+            variable: "f".to_string(),
+            source: LoopSource::Positional,
+            body: vec![Command::ForEach {
+                variable: "d".to_string(),
+                source: LoopSource::Option(long!("define")),
+                body: vec![If {
+                    condition: Condition::Has(short!('v')),
+                    then: vec![Add { option: long!("log"), values: sv!["${f}:${d}"] }],
+                    otherwise: vec![],
+                }],
+            }],
+        }
+    );
+
+    #[test]
+    fn parse_bad_for_loops() {
+        assert_err!(PARSER.parse_from_script("for x in opt:I\nadd a"));
+        assert_err!(PARSER.parse_from_script("for x in I\nend"));
+        assert_err!(PARSER.parse_from_script("for x in positionals\nend"));
+        assert_err!(PARSER.parse_from_script("for x in positional\nelse\nend"));
+    }
+
+    #[test]
+    fn parse_bad_if_blocks() {
+        assert_err!(PARSER.parse_from_script("if has ci\nadd a"));
+        assert_err!(PARSER.parse_from_script("add a\nend"));
+        assert_err!(PARSER.parse_from_script("if has ci\nelse\nelse\nend"));
+        assert_err!(PARSER.parse_from_script("if ci\nend"));
+        assert_err_contains!(PARSER.parse_script("if has ci\n@target ls\nend").map_err(|e| e.root_cause().to_string()),
+                             "not allowed inside blocks");
+    }
+
+    test_cmds!(replace, r"replace I /^\/home\/build\//\/workspace\//", "replace include /(a)#/$1/gl",
+            "pos.replace /x/y/i" =>
+        Replace { option: short!('I'), substitution: Substitution::new("^/home/build/", "/workspace/", "").unwrap() },
+        Replace { option: long!("include"), substitution: Substitution::new("(a)#", "$1", "gl").unwrap() },
+        ReplacePositional { substitution: Substitution::new("x", "y", "i").unwrap() }
+    );
+
+    #[test]
+    fn parse_bad_replace() {
+        assert_err!(PARSER.parse_from_args(["replace a /x/y"].iter()));
+        assert_err!(PARSER.parse_from_args(["replace a //y/"].iter()));
+        let root_cause = |command: &str| {
+            PARSER.parse_from_args([command].iter()).map_err(|e| e.root_cause().to_string())
+        };
+        assert_err_contains!(root_cause("replace a /(/y/"), "regex parse error");
+        assert_err_contains!(root_cause("pos.replace /x/y/q"), "Unknown substitution flag");
+    }
+
+    test_cmds!(remove_matching, "remove-matching 'debug-*'", r"remove /^W\/x/", r"remove-matching /^f/",
+            "remove 'debug-*'", "remove \"long name\"", "remove v" =>
+        RemoveMatching { pattern: Pattern::from_glob("debug-*").unwrap() },
+        RemoveMatching { pattern: Pattern::from_regex("^W/x").unwrap() },
+        RemoveMatching { pattern: Pattern::from_regex("^f").unwrap() },
+        Remove { option: long!("debug-*") },
+        Remove { option: long!("long name") },
+        Remove { option: short!('v') }
+    );
+
+    test_cmds!(positional, "pos.prepend ./src", "pos.append a 'b c'", "pos.insert -1 x", "pos.remove 0",
+        "pos.remove -2", "pos.remove '0' other", r"pos.remove /\.o$/", "pos.clear" =>
+        Command::InsertPositional { index: 0, values: sv!["./src"] },
+        Command::AppendPositional { values: sv!["a", "b c"] },
+        Command::InsertPositional { index: -1, values: sv!["x"] },
+        Command::RemovePositional { index: 0 },
+        Command::RemovePositional { index: -2 },
+        Command::RemovePositionalValues { values: sv!["0", "other"] },
+        Command::RemovePositionalMatching { pattern: Pattern::from_regex(r"\.o$").unwrap() },
+        Command::ClearPositional
+    );
+
+    test_cmds!(prepend, "prepend ':' PATH=/opt/bin /usr/local/bin", "prepend name=prefix-" =>
+        Command::Prepend {
+            option: long!("PATH"),
+            delimiter: ":".to_string(),
+            items: sv!["/opt/bin", "/usr/local/bin"],
+        },
+        Command::Prepend { option: long!("name"), delimiter: "".to_string(), items: sv!["prefix-"] }
+    );
+
+    test_cmds!(unappend, "unappend ',' features=b c", "unappend drop-empty ':' PATH /^\\/tmp/" =>
+        Command::Unappend {
+            option: long!("features"),
+            delimiter: ",".to_string(),
+            items: ValueSelector::Exact(sv!["b", "c"]),
+            drop_empty: false,
+        },
+        Command::Unappend {
+            option: long!("PATH"),
+            delimiter: ":".to_string(),
+            items: ValueSelector::Matching(Pattern::from_regex("^/tmp").unwrap()),
+            drop_empty: true,
+        }
+    );
+
+    test_cmds!(dedupe, "dedupe", "dedupe last", "dedupe I", "dedupe last define", "dedupe 'last'", "dedupe lastly",
+        "dedupe-values PATH ':'", "dedupe-values last features ','" =>
+        Command::Dedupe { option: None, keep: Occurrence::First },
+        Command::Dedupe { option: None, keep: Occurrence::Last },
+        Command::Dedupe { option: Some(short!('I')), keep: Occurrence::First },
+        Command::Dedupe { option: Some(long!("define")), keep: Occurrence::Last },
+        Command::Dedupe { option: Some(long!("last")), keep: Occurrence::First },
+        Command::Dedupe { option: Some(long!("lastly")), keep: Occurrence::First },
+        Command::DedupeValues { option: long!("PATH"), delimiter: ":".to_string(), keep: Occurrence::First },
+        Command::DedupeValues { option: long!("features"), delimiter: ",".to_string(), keep: Occurrence::Last }
+    );
+
+    test_cmds!(variables, "let out = ${OUT_DIR:-build}", "let name='${opt:name}.o'", "set output=${out}/${name}" =>
+        Command::Let { name: "out".to_string(), value: "${OUT_DIR:-build}".to_string() },
+        Command::Let { name: "name".to_string(), value: "${opt:name}.o".to_string() },
+        Set { option: long!("output"), values: sv!["${out}/${name}"] }
+    );
+
+    #[test]
+    fn parse_script_with_header() -> eyre::Result<()> {
+        let (header, commands) = PARSER.parse_script(r###"#!/usr/bin/env -S oco --run
+            # Header directives come before the commands
+            @target /usr/bin/ls  # Trailing comment
+            @input-dialect gnu:positional=free
+            @output-dialect 'gnu:-grouping'
+            @vocabulary ls.vocab
+            @option c|color
+
+            set color=always
+        "###)?;
+
+        let mut vocabulary = Vocabulary::default();
+        vocabulary.add_option("c|color")?;
+        assert_eq!(header, ScriptHeader {
+            target: Some("/usr/bin/ls".into()),
+            input_dialect: Some(Dialect::Gnu(Gnu { positional: PositionalArgumentsMode::Free, ..Gnu::default() })),
+            output_dialect: Some(Dialect::Gnu(Gnu { grouping: false, ..Gnu::default() })),
+            vocabulary_files: vec!["ls.vocab".into()],
+            vocabulary,
+        });
+        assert_eq!(commands, vec![Set { option: long!("color"), values: sv!["always"] }]);
+        Ok(())
+    }
+
+    #[test]
+    fn parse_script_with_bad_header() {
+        assert_err_contains!(PARSER.parse_script("set a\n@target ls"), "must come before all commands");
+        assert_err_contains!(PARSER.parse_script("macro m()\nend\n@target ls"), "must come before all commands");
+        assert_err_contains!(PARSER.parse_script("@unknown value"), "Bad header directive");
+        assert_err_contains!(PARSER.parse_script("@target ls\n@target ls").map_err(|e| e.root_cause().to_string()),
+                             "more than once");
+
+        // Headers are only supported when running a script
+        assert_err_contains!(PARSER.parse_from_script("@target ls\nset a"), "--run");
+    }
+}
@@ -0,0 +1,60 @@
+use std::path::PathBuf;
+use eyre::{bail, Context, Result};
+
+use crate::cli::Dialect;
+use crate::opts::vocabulary::Vocabulary;
+
+/// Configuration from the header of a script that is run as a program (see `oco --run`).
+///
+/// Header directives are written as `@name value` lines, before the first command of the script.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct ScriptHeader {
+    /// The program to run with the processed arguments (`@target`)
+    pub target: Option<PathBuf>,
+
+    /// `@input-dialect`
+    pub input_dialect: Option<Dialect>,
+
+    /// `@output-dialect`
+    pub output_dialect: Option<Dialect>,
+
+    /// Vocabulary files to load, relative to the script (`@vocabulary`)
+    pub vocabulary_files: Vec<PathBuf>,
+
+    /// Options and exclusive groups defined directly in the header (`@option` and `@exclusive`)
+    pub vocabulary: Vocabulary,
+}
+
+impl ScriptHeader {
+    pub fn is_empty(&self) -> bool {
+        *self == Self::default()
+    }
+
+    pub fn add_directive(&mut self, name: &str, value: String) -> Result<()> {
+        match name {
+            "target" => {
+                if self.target.is_some() {
+                    bail!("Target program is specified more than once");
+                }
+                self.target = Some(value.into());
+            }
+            "input-dialect" => {
+                self.input_dialect = Some(value.parse().wrap_err("Bad input dialect")?);
+            }
+            "output-dialect" => {
+                self.output_dialect = Some(value.parse().wrap_err("Bad output dialect")?);
+            }
+            "vocabulary" => {
+                self.vocabulary_files.push(value.into());
+            }
+            "option" => {
+                self.vocabulary.add_option(&value)?;
+            }
+            "exclusive" => {
+                self.vocabulary.add_exclusive_group(&value)?;
+            }
+            _ => bail!("Unknown header directive: @{name}"),
+        }
+        Ok(())
+    }
+}
@@ -1,8 +1,22 @@
 mod common;
+mod condition;
+mod context;
+mod header;
 mod compact;
+mod pattern;
 mod parser_exts;
+mod number;
+mod substitution;
+mod transform;
 mod full;
 
-pub use common::{Command, CommandParser};
+pub use common::{AssertionError, Command, CommandParser, LoopSource, Occurrence, Position, SortOrder};
+pub use condition::Condition;
+pub use context::Context;
+pub use header::ScriptHeader;
+pub use pattern::{Pattern, ValueSelector};
+pub use number::Number;
+pub use substitution::Substitution;
+pub use transform::Transform;
 pub use compact::CompactCommandParser;
 pub use full::FullCommandParser;
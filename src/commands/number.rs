@@ -0,0 +1,250 @@
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
+use eyre::{bail, eyre, Result};
+use itertools::Itertools;
+
+/// A number with an optional unit suffix, e.g. `8`, `2.5`, `512M` or `30s`.
+///
+/// Sizes are written with `K`, `M`, `G` or `T` (powers of 1024, in either case), and durations with `s`, `m`, `h` or
+/// `d`. A lowercase `m` is a size or a duration, depending on the number it is used with. Integers are handled exactly,
+/// while other numbers are computed as floating point, and rounded to the precision they were written with.
+#[derive(Clone, Debug)]
+pub struct Number {
+    text: String,
+    magnitude: f64,
+    /// The exact magnitude, if it is an integer
+    integer: Option<i128>,
+    /// The number of digits after the decimal point
+    decimals: usize,
+    unit: String,
+}
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+enum UnitKind {
+    Size,
+    Duration,
+}
+
+impl Number {
+    pub fn parse(text: &str) -> Result<Self> {
+        let unit_start = text.find(|c: char| c.is_ascii_alphabetic()).unwrap_or(text.len());
+        let (magnitude, unit) = text.split_at(unit_start);
+        let integer = magnitude.parse().ok();
+        let decimals = decimals(magnitude);
+        let magnitude: f64 = magnitude.parse().map_err(|_| eyre!("Not a number: {text}"))?;
+        if unit_kinds(unit).next().is_none() {
+            bail!("Unknown unit '{unit}' in: {text}");
+        }
+        Ok(Number { text: text.to_string(), magnitude, integer, decimals, unit: unit.to_string() })
+    }
+
+    /// Limits a value to an inclusive range, keeping its text if it is within the range (otherwise the text of the
+    /// bound it exceeds is used)
+    pub fn clamp(value: &str, min: Option<&Number>, max: Option<&Number>) -> Result<String> {
+        let value = Number::parse(value)?;
+        if let Some(min) = min {
+            if value.compare(min)? == Ordering::Less {
+                return Ok(min.text.clone());
+            }
+        }
+        if let Some(max) = max {
+            if value.compare(max)? == Ordering::Greater {
+                return Ok(max.text.clone());
+            }
+        }
+        Ok(value.text)
+    }
+
+    /// Adds this number to a value, keeping the unit of the value
+    pub fn add_to(&self, value: &str) -> Result<String> {
+        let value = Number::parse(value)?;
+        let (converted, integer) = value.convert(self)?;
+        if let Some(sum) = value.integer.zip(integer).and_then(|(value, converted)| value.checked_add(converted)) {
+            return Ok(format!("{sum}{}", value.unit));
+        }
+        let sum = value.magnitude + converted;
+        let (from, to) = value.scales(self)?;
+        let decimals = value.decimals.max(self.decimals_in(from, to));
+        let sum = format!("{sum:.decimals$}");
+        let sum = if sum.contains('.') { sum.trim_end_matches('0').trim_end_matches('.') } else { &sum };
+        Ok(format!("{}{}", if sum == "-0" { "0" } else { sum }, value.unit))
+    }
+
+    /// Compares the magnitude of this number with another number, in the unit of this number
+    fn compare(&self, other: &Number) -> Result<Ordering> {
+        let (converted, integer) = self.convert(other)?;
+        Ok(match self.integer.zip(integer) {
+            Some((integer, converted)) => integer.cmp(&converted),
+            None => self.magnitude.partial_cmp(&converted).unwrap_or(Ordering::Equal),
+        })
+    }
+
+    /// Converts the magnitude of another number into the unit of this number, along with its exact value if it is an
+    /// integer in this unit
+    fn convert(&self, other: &Number) -> Result<(f64, Option<i128>)> {
+        if self.unit == other.unit {
+            return Ok((other.magnitude, other.integer));
+        }
+        let (from, to) = self.scales(other)?;
+        let integer = other.integer
+                .and_then(|integer| integer.checked_mul(from.into()))
+                .filter(|scaled| scaled % i128::from(to) == 0)
+                .map(|scaled| scaled / i128::from(to));
+        Ok((other.magnitude * from as f64 / to as f64, integer))
+    }
+
+    /// The scales of the units of another number and of this number, in the base unit of their kind
+    fn scales(&self, other: &Number) -> Result<(u64, u64)> {
+        if self.unit == other.unit {
+            return Ok((1, 1));
+        }
+        let kinds = unit_kinds(&self.unit).filter(|&kind| unit_scale(&other.unit, kind).is_some()).collect_vec();
+        match kinds.as_slice() {
+            [kind] => {
+                let scale = |unit| unit_scale(unit, *kind).unwrap_or(1);
+                Ok((scale(&other.unit), scale(&self.unit)))
+            }
+            [] => bail!("Cannot compare {} with {}, since their units are incompatible", self.text, other.text),
+            _ => bail!("Cannot compare {} with {}, since their units are ambiguous", self.text, other.text),
+        }
+    }
+
+    /// The number of digits after the decimal point needed to write this number in another unit. That is exact if every
+    /// multiple of its last written digit has a finite decimal expansion in that unit (e.g. `512M` is `0.5G`), and
+    /// otherwise keeps the precision it was written with (e.g. `20m` is `0.33h`).
+    fn decimals_in(&self, from: u64, to: u64) -> usize {
+        // The last written digit is worth `from / (10^decimals * to)` in the other unit
+        let Some(denominator) = u32::try_from(self.decimals).ok()
+                .and_then(|decimals| 10u128.checked_pow(decimals))
+                .and_then(|power| power.checked_mul(to.into())) else {
+            return self.decimals;
+        };
+        let gcd = gcd(from.into(), denominator);
+        let (numerator, denominator) = (u128::from(from) / gcd, denominator / gcd);
+
+        let (mut rest, mut factors) = (denominator, [0, 0]);
+        for (factor, count) in [2, 5].into_iter().zip(&mut factors) {
+            while rest % factor == 0 {
+                rest /= factor;
+                *count += 1;
+            }
+        }
+        if rest == 1 {
+            factors[0].max(factors[1])
+        } else {
+            (denominator as f64 / numerator as f64).log10().ceil().max(0.0) as usize
+        }
+    }
+}
+
+/// Formats the number as it was written
+impl Display for Number {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.text)
+    }
+}
+
+impl PartialEq for Number {
+    fn eq(&self, other: &Self) -> bool {
+        self.text == other.text
+    }
+}
+
+impl Eq for Number {}
+
+fn gcd(a: u128, b: u128) -> u128 {
+    if b == 0 { a } else { gcd(b, a % b) }
+}
+
+/// The number of digits after the decimal point
+fn decimals(magnitude: &str) -> usize {
+    magnitude.split_once('.').map_or(0, |(_, fraction)| fraction.len())
+}
+
+fn unit_kinds(unit: &str) -> impl Iterator<Item=UnitKind> + '_ {
+    [UnitKind::Size, UnitKind::Duration].into_iter().filter(move |&kind| unit_scale(unit, kind).is_some())
+}
+
+/// The number of base units (bytes or seconds) that a unit stands for, if it is a unit of the given kind
+fn unit_scale(unit: &str, kind: UnitKind) -> Option<u64> {
+    match (kind, unit) {
+        (_, "") => Some(1),
+        (UnitKind::Size, "K" | "k") => Some(1024),
+        (UnitKind::Size, "M" | "m") => Some(1024u64.pow(2)),
+        (UnitKind::Size, "G" | "g") => Some(1024u64.pow(3)),
+        (UnitKind::Size, "T" | "t") => Some(1024u64.pow(4)),
+        (UnitKind::Duration, "s") => Some(1),
+        (UnitKind::Duration, "m") => Some(60),
+        (UnitKind::Duration, "h") => Some(3600),
+        (UnitKind::Duration, "d") => Some(86400),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::commands::Number;
+    use crate::util::testing::assert_err_contains;
+
+    #[test]
+    fn test_parse() {
+        assert!(Number::parse("8").is_ok());
+        assert!(Number::parse("-2.5").is_ok());
+        assert!(Number::parse("512M").is_ok());
+        assert!(Number::parse("1.5h").is_ok());
+        assert_err_contains!(Number::parse("auto"), "Not a number");
+        assert_err_contains!(Number::parse("1e3"), "Unknown unit");
+        assert_err_contains!(Number::parse("30x"), "Unknown unit");
+        assert_err_contains!(Number::parse("inf"), "Not a number");
+    }
+
+    #[test]
+    fn test_clamp() -> Result<()> {
+        let (one, eight) = (Number::parse("1")?, Number::parse("8")?);
+        assert_eq!(Number::clamp("16", Some(&one), Some(&eight))?, "8");
+        assert_eq!(Number::clamp("0", Some(&one), Some(&eight))?, "1");
+        assert_eq!(Number::clamp("4", Some(&one), None)?, "4");
+        assert_eq!(Number::clamp("2.5", None, Some(&eight))?, "2.5");
+
+        let (min_memory, max_memory) = (Number::parse("512M")?, Number::parse("2G")?);
+        assert_eq!(Number::clamp("256m", Some(&min_memory), Some(&max_memory))?, "512M");
+        assert_eq!(Number::clamp("4096M", Some(&min_memory), Some(&max_memory))?, "2G");
+        assert_eq!(Number::clamp("1g", Some(&min_memory), Some(&max_memory))?, "1g");
+
+        let min_timeout = Number::parse("30s")?;
+        assert_eq!(Number::clamp("1m", Some(&min_timeout), None)?, "1m");
+        assert_eq!(Number::clamp("20", Some(&min_timeout), None)?, "30s");
+
+        let max_id = Number::parse("9007199254740992")?;
+        assert_eq!(Number::clamp("9007199254740993", None, Some(&max_id))?, "9007199254740992");
+
+        assert_err_contains!(Number::clamp("1G", Some(&min_timeout), None), "incompatible");
+        assert_err_contains!(Number::clamp("5m", Some(&one), None), "ambiguous");
+        assert_err_contains!(Number::clamp("many", Some(&one), None), "Not a number");
+        Ok(())
+    }
+
+    #[test]
+    fn test_add_to() -> Result<()> {
+        assert_eq!(Number::parse("1")?.add_to("3")?, "4");
+        assert_eq!(Number::parse("-5")?.add_to("3")?, "-2");
+        assert_eq!(Number::parse("-0.5")?.add_to("3")?, "2.5");
+        assert_eq!(Number::parse("0.5")?.add_to("1.5")?, "2");
+        assert_eq!(Number::parse("30m")?.add_to("1h")?, "1.5h");
+        assert_eq!(Number::parse("512M")?.add_to("1G")?, "1.5G");
+        assert_eq!(Number::parse("1G")?.add_to("512M")?, "1536M");
+        assert_eq!(Number::parse("1")?.add_to("9007199254740993")?, "9007199254740994");
+        assert_eq!(Number::parse("0.2")?.add_to("0.1")?, "0.3");
+        assert_eq!(Number::parse("0.05")?.add_to("1.25")?, "1.3");
+        assert_eq!(Number::parse("-0.1")?.add_to("0.1")?, "0");
+        // Conversions that cannot be written exactly keep the precision of the number that is added
+        assert_eq!(Number::parse("20m")?.add_to("1h")?, "1.33h");
+        assert_eq!(Number::parse("20.5m")?.add_to("1h")?, "1.342h");
+        assert_eq!(Number::parse("1s")?.add_to("1.5h")?, "1.5003h");
+        assert_eq!(Number::parse("1M")?.add_to("1G")?, "1.0009765625G");
+        assert_err_contains!(Number::parse("1s")?.add_to("1G"), "incompatible");
+        Ok(())
+    }
+}
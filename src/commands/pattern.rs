@@ -0,0 +1,136 @@
+use eyre::{bail, Context, Result};
+use itertools::Itertools;
+use regex::Regex;
+
+use crate::commands::substitution::unescape_slashes;
+use crate::opts::parsed_args::{OptMatcher, OptName};
+
+/// Matches values or option names (as written in scripts, without any prefix) by a glob or a regular expression
+#[derive(Clone, Debug)]
+pub struct Pattern {
+    regex: Regex,
+}
+
+impl Pattern {
+    /// Creates a pattern from a glob, supporting `*`, `?` and character classes (`[abc]`, `[!abc]`). As in shells, a
+    /// `]` right after the opening bracket is part of the class (e.g. `[]x]`), so classes are never empty.
+    pub fn from_glob(glob: &str) -> Result<Self> {
+        let mut regex = String::from("^");
+        let mut chars = glob.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '*' => regex.push_str(".*"),
+                '?' => regex.push('.'),
+                '[' => {
+                    let negated = if chars.next_if_eq(&'!').is_some() { "^" } else { "" };
+                    let first = chars.next_if_eq(&']');
+                    let class = first.into_iter().chain(chars.take_while_ref(|&c| c != ']')).collect::<String>();
+                    if chars.next().is_none() {
+                        bail!("Unterminated character class in option name pattern: {glob}");
+                    }
+                    let class = class.chars().map(|c| match c {
+                        '\\' | '[' | ']' | '^' | '&' | '~' => format!("\\{c}"),
+                        _ => c.to_string(),
+                    });
+                    regex.push_str(&format!("[{negated}{}]", class.collect::<String>()));
+                }
+                _ => regex.push_str(&regex::escape(&c.to_string())),
+            }
+        }
+        regex.push('$');
+
+        let regex = Regex::new(&regex).wrap_err_with(|| format!("Invalid option name pattern: {glob}"))?;
+        Ok(Pattern { regex })
+    }
+
+    /// Creates a pattern from a regular expression, as written between slashes (where `\/` stands for `/`)
+    pub fn from_regex(pattern: &str) -> Result<Self> {
+        let pattern = unescape_slashes(pattern);
+        let regex = Regex::new(&pattern).wrap_err_with(|| format!("Invalid regular expression: {pattern}"))?;
+        Ok(Pattern { regex })
+    }
+}
+
+impl Pattern {
+    pub fn is_match(&self, value: &str) -> bool {
+        self.regex.is_match(value)
+    }
+}
+
+impl OptMatcher for Pattern {
+    fn matches(&self, name: &OptName) -> bool {
+        self.is_match(&name.unprefixed())
+    }
+}
+
+/// Selects values, either by their exact value or by a pattern
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ValueSelector {
+    Exact(Vec<String>),
+    Matching(Pattern),
+}
+
+impl ValueSelector {
+    pub fn matches(&self, value: &str) -> bool {
+        match self {
+            ValueSelector::Exact(values) => values.iter().any(|v| v == value),
+            ValueSelector::Matching(pattern) => pattern.is_match(value),
+        }
+    }
+}
+
+// Regex does not implement Eq, so patterns are compared by their regular expression
+impl PartialEq for Pattern {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+    }
+}
+
+impl Eq for Pattern {}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use crate::commands::Pattern;
+    use crate::opts::parsed_args::OptMatcher;
+    use crate::util::testing::assert_err_contains;
+    use crate::util::testing::opts::name::{long, short};
+
+    #[test]
+    fn test_glob() -> Result<()> {
+        let pattern = Pattern::from_glob("debug-*")?;
+        assert!(pattern.matches(&long!("debug-info")));
+        assert!(pattern.matches(&long!("debug-")));
+        assert!(!pattern.matches(&long!("no-debug-info")));
+
+        let pattern = Pattern::from_glob("W?[!x]")?;
+        assert!(pattern.matches(&long!("Wab")));
+        assert!(!pattern.matches(&long!("Wax")));
+        assert!(!pattern.matches(&short!('W')));
+
+        // Regex syntax is escaped
+        let pattern = Pattern::from_glob("a.b[.]")?;
+        assert!(pattern.matches(&long!("a.b.")));
+        assert!(!pattern.matches(&long!("axbx")));
+        let pattern = Pattern::from_glob("[]^&&a-c]")?;
+        assert!(pattern.matches(&long!("]")) && pattern.matches(&long!("^")) && pattern.matches(&long!("&")));
+        assert!(pattern.matches(&long!("b")) && !pattern.matches(&long!("d")));
+        assert!(Pattern::from_glob("[!]]")?.matches(&long!("x")));
+        assert!(!Pattern::from_glob("[!]]")?.matches(&long!("]")));
+
+        assert_err_contains!(Pattern::from_glob("debug-[ab"), "Unterminated character class");
+        assert_err_contains!(Pattern::from_glob("debug-[]"), "Unterminated character class");
+        assert_err_contains!(Pattern::from_glob("debug-[!"), "Unterminated character class");
+        Ok(())
+    }
+
+    #[test]
+    fn test_regex() -> Result<()> {
+        let pattern = Pattern::from_regex("^W")?;
+        assert!(pattern.matches(&short!('W')));
+        assert!(pattern.matches(&long!("Wall")));
+        assert!(!pattern.matches(&long!("all")));
+        assert!(Pattern::from_regex(r"^a\/b$")?.matches(&long!("a/b")));
+        Ok(())
+    }
+}
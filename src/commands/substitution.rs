@@ -0,0 +1,123 @@
+use std::borrow::Cow;
+use eyre::{bail, Context, Result};
+use regex::{Regex, RegexBuilder};
+
+/// A regular expression substitution, written as `/pattern/replacement/flags`.
+///
+/// The replacement can refer to capture groups (`$1`, `${name}`). Supported flags:
+/// - `g`: replace all matches in a value (otherwise only the first one)
+/// - `i`: match case-insensitively
+/// - `l`: only apply to the last matching argument (otherwise to all of them)
+#[derive(Clone, Debug)]
+pub struct Substitution {
+    pub regex: Regex,
+    pub replacement: String,
+    pub all_matches: bool,
+    pub last_only: bool,
+}
+
+impl Substitution {
+    /// Creates a substitution from its parts, as written between the slashes (where `\/` stands for `/`)
+    pub fn new(pattern: &str, replacement: &str, flags: &str) -> Result<Self> {
+        let (mut all_matches, mut case_insensitive, mut last_only) = (false, false, false);
+        for flag in flags.chars() {
+            match flag {
+                'g' => all_matches = true,
+                'i' => case_insensitive = true,
+                'l' => last_only = true,
+                _ => bail!("Unknown substitution flag: {flag}"),
+            }
+        }
+
+        let pattern = unescape_slashes(pattern);
+        let regex = RegexBuilder::new(&pattern)
+                .case_insensitive(case_insensitive)
+                .build()
+                .wrap_err_with(|| format!("Invalid regular expression: {pattern}"))?;
+        Ok(Substitution { regex, replacement: unescape_slashes(replacement), all_matches, last_only })
+    }
+
+    pub fn apply_in_place(&self, value: &mut String) {
+        let replaced = match self.apply(value) {
+            Cow::Owned(replaced) => replaced,
+            Cow::Borrowed(_) => return, // Nothing matched
+        };
+        *value = replaced;
+    }
+
+    /// The maximal number of arguments to apply the substitution to (counting from the last one)
+    pub fn limit(&self) -> usize {
+        if self.last_only { 1 } else { usize::MAX }
+    }
+
+    pub fn apply<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        if self.all_matches {
+            self.regex.replace_all(value, self.replacement.as_str())
+        } else {
+            self.regex.replace(value, self.replacement.as_str())
+        }
+    }
+}
+
+// Regex does not implement Eq, so substitutions are compared by their pattern
+impl PartialEq for Substitution {
+    fn eq(&self, other: &Self) -> bool {
+        self.regex.as_str() == other.regex.as_str()
+                && self.replacement == other.replacement
+                && self.all_matches == other.all_matches
+                && self.last_only == other.last_only
+    }
+}
+
+impl Eq for Substitution {}
+
+pub(super) fn unescape_slashes(str: &str) -> String {
+    let mut result = String::with_capacity(str.len());
+    let mut chars = str.chars();
+    while let Some(c) = chars.next() {
+        match (c, chars.clone().next()) {
+            ('\\', Some('/')) => {
+                result.push('/');
+                chars.next();
+            }
+            ('\\', Some(escaped)) => {
+                result.push('\\');
+                result.push(escaped);
+                chars.next();
+            }
+            _ => result.push(c),
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::commands::substitution::Substitution;
+    use crate::util::testing::assert_err_contains;
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        let substitution = Substitution::new("/home/build/", "/workspace/", "")?;
+        assert_eq!(substitution.apply("/home/build/src:/home/build/lib"), "/workspace/src:/home/build/lib");
+
+        let substitution = Substitution::new(r"\/home\/(\w+)/", "/users/$1/", "g")?;
+        assert_eq!(substitution.apply("/home/a/x:/home/b/y"), "/users/a/x:/users/b/y");
+
+        let substitution = Substitution::new("debug", "info", "i")?;
+        assert_eq!(substitution.apply("DEBUG"), "info");
+
+        // Other escapes are kept for the regular expression
+        let substitution = Substitution::new(r"\d\\", "N", "")?;
+        assert_eq!(substitution.apply(r"1\2\"), r"N2\");
+        Ok(())
+    }
+
+    #[test]
+    fn test_bad_substitutions() {
+        assert_err_contains!(Substitution::new("(", "", ""), "Invalid regular expression");
+        assert_err_contains!(Substitution::new("a", "", "x"), "Unknown substitution flag");
+    }
+}
@@ -0,0 +1,133 @@
+use std::path::{Component, Path, PathBuf};
+use eyre::{Context, Result};
+
+/// A string function that is applied to values (see `derive`, `normalize-path`, `lower` and `trim`)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Transform {
+    /// The parent directory of a path (`.` if it has none)
+    Dirname,
+    /// The last component of a path
+    Basename,
+    /// Removes a suffix, if the value ends with it
+    StripSuffix(String),
+    Lower,
+    Upper,
+    /// Removes leading and trailing whitespace
+    Trim,
+    /// Removes `.` components, trailing slashes and repeated slashes from a path, and resolves `..` components
+    /// lexically (without following symbolic links). With a directory (which can reference variables), the path is
+    /// made relative to it, resolving relative paths against the working directory.
+    NormalizePath { relative_to: Option<String> },
+}
+
+impl Transform {
+    pub fn apply(&self, value: &str) -> Result<String> {
+        let transformed = match self {
+            Transform::Dirname => match Path::new(value).parent().map(|parent| parent.to_string_lossy()) {
+                Some(parent) if !parent.is_empty() => parent.into_owned(),
+                Some(_) => ".".to_string(),
+                None => value.to_string(), // The root, or an empty path
+            },
+            Transform::Basename => {
+                Path::new(value).file_name().map_or(value.to_string(), |name| name.to_string_lossy().into_owned())
+            }
+            Transform::StripSuffix(suffix) => value.strip_suffix(suffix.as_str()).unwrap_or(value).to_string(),
+            Transform::Lower => value.to_lowercase(),
+            Transform::Upper => value.to_uppercase(),
+            Transform::Trim => value.trim().to_string(),
+            Transform::NormalizePath { .. } if value.is_empty() => String::new(), // Not a path, e.g. `--prefix=`
+            Transform::NormalizePath { relative_to: None } => normalize_path(Path::new(value)).to_string_lossy().into(),
+            Transform::NormalizePath { relative_to: Some(dir) } => {
+                let absolute = |path: &str| std::path::absolute(path)
+                        .map(|path| normalize_path(&path))
+                        .wrap_err_with(|| format!("Failed to resolve path: {path}"));
+                relative_path(&absolute(value)?, &absolute(dir)?).to_string_lossy().into()
+            }
+        };
+        Ok(transformed)
+    }
+}
+
+/// Normalizes a path without accessing the filesystem (see [Transform::NormalizePath])
+fn normalize_path(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir => match normalized.components().next_back() {
+                Some(Component::Normal(_)) => {
+                    normalized.pop();
+                }
+                Some(Component::RootDir | Component::Prefix(_)) => {} // The parent of the root is the root
+                _ => normalized.push(".."),
+            },
+            _ => normalized.push(component),
+        }
+    }
+    if normalized.as_os_str().is_empty() {
+        normalized.push(".");
+    }
+    normalized
+}
+
+/// Returns a path relative to a directory, given both as normalized absolute paths
+fn relative_path(path: &Path, dir: &Path) -> PathBuf {
+    let common = path.components().zip(dir.components()).take_while(|(a, b)| a == b).count();
+    let relative: PathBuf = dir.components().skip(common).map(|_| Component::ParentDir)
+            .chain(path.components().skip(common))
+            .collect();
+    if relative.as_os_str().is_empty() {
+        PathBuf::from(".")
+    } else {
+        relative
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::commands::Transform;
+
+    #[test]
+    fn test_apply() -> Result<()> {
+        assert_eq!(Transform::Dirname.apply("build/out/main.o")?, "build/out");
+        assert_eq!(Transform::Dirname.apply("/main.o")?, "/");
+        assert_eq!(Transform::Dirname.apply("main.o")?, ".");
+        assert_eq!(Transform::Dirname.apply("/")?, "/");
+        assert_eq!(Transform::Basename.apply("build/out/main.o")?, "main.o");
+        assert_eq!(Transform::Basename.apply("build/out/")?, "out");
+        assert_eq!(Transform::StripSuffix(".o".to_string()).apply("main.o")?, "main");
+        assert_eq!(Transform::StripSuffix(".o".to_string()).apply("main.c")?, "main.c");
+        assert_eq!(Transform::Lower.apply("Release")?, "release");
+        assert_eq!(Transform::Upper.apply("Release")?, "RELEASE");
+        assert_eq!(Transform::Trim.apply(" \tsrc/main.c \n")?, "src/main.c");
+        Ok(())
+    }
+
+    #[test]
+    fn test_normalize_path() -> Result<()> {
+        let normalize = Transform::NormalizePath { relative_to: None };
+        assert_eq!(normalize.apply("./src//main.c")?, "src/main.c");
+        assert_eq!(normalize.apply("build/out/")?, "build/out");
+        assert_eq!(normalize.apply("build/../src/./lib/..")?, "src");
+        assert_eq!(normalize.apply("../../src")?, "../../src");
+        assert_eq!(normalize.apply("src/../..")?, "..");
+        assert_eq!(normalize.apply("/usr/../../lib/")?, "/lib");
+        assert_eq!(normalize.apply("./")?, ".");
+        assert_eq!(normalize.apply("")?, "");
+
+        let relative_to = |dir: &str| Transform::NormalizePath { relative_to: Some(dir.to_string()) };
+        assert_eq!(relative_to("/work/project").apply("/work/project/src/main.c")?, "src/main.c");
+        assert_eq!(relative_to("/work/project/").apply("/work/lib/./util.c")?, "../lib/util.c");
+        assert_eq!(relative_to("/work/project").apply("/work/project")?, ".");
+        assert_eq!(relative_to("/work/project").apply("/usr/include")?, "../../usr/include");
+        assert_eq!(relative_to("/work/project").apply("")?, "");
+
+        let current_dir = std::env::current_dir()?;
+        let current_dir = current_dir.to_str().unwrap();
+        assert_eq!(relative_to(current_dir).apply("./src/main.c")?, "src/main.c");
+        assert_eq!(relative_to("src").apply(&format!("{current_dir}/src/main.c"))?, "main.c");
+        Ok(())
+    }
+}
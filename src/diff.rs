@@ -0,0 +1,429 @@
+use std::io::{BufReader, Write};
+use clio::Input;
+use eyre::{Context, Result};
+use itertools::Itertools;
+
+use crate::cli::{Dialect, DiffSpec};
+use crate::opts::grammar::Grammar;
+use crate::opts::parsed_args::{OptName, ParsedArgs, ParsedOpt};
+use crate::opts::vocabulary::Vocabulary;
+use crate::read_args::{read_args, read_cmdline};
+use crate::util::shell;
+
+/// Compares the two argument lists of the spec, writing either their differences or a script that transforms the first
+/// one into the second one to the output.
+///
+/// Returns `false` if the argument lists are equivalent, so the caller can report it through the exit status.
+pub fn diff<W: Write>(spec: DiffSpec, dialect: &Dialect, vocabulary: &Vocabulary, out: &mut W) -> Result<bool> {
+    let from = read_input(spec.from, spec.cmdline, dialect)?;
+    let to = read_input(spec.to, spec.cmdline, dialect)?;
+
+    let diff = ArgsDiff::new(&from, &to, vocabulary);
+    if spec.script {
+        diff.write_script(out)?;
+    } else {
+        diff.write_report(dialect, out)?;
+    }
+    Ok(!diff.is_empty())
+}
+
+/// The exit status for the result of [diff], as with diff(1): 0 if the argument lists are equivalent, 1 if they differ
+/// and 2 if they could not be compared
+pub fn diff_status(result: &Result<bool>) -> u8 {
+    match result {
+        Ok(false) => 0,
+        Ok(true) => 1,
+        Err(_) => 2,
+    }
+}
+
+fn read_input(input: Input, cmdline: bool, dialect: &Dialect) -> Result<ParsedArgs> {
+    let path = input.path().to_string_lossy().into_owned();
+    let args = if cmdline {
+        let (_program, args) = read_cmdline(input)
+                .wrap_err_with(|| format!("Failed to read command line file: {path}"))?;
+        args
+    } else {
+        read_args(BufReader::new(input)).collect()
+    };
+    dialect.parse(args.into_iter()).wrap_err_with(|| format!("Failed to parse arguments from: {path}"))
+}
+
+/// The differences between two argument lists.
+///
+/// Options are compared by name (including aliases from the vocabulary), ignoring the relative order of different
+/// options. The occurrences of each option are compared in order, unless the vocabulary declares the option as
+/// `unordered`. Positional arguments are always compared in order.
+#[derive(Debug, Eq, PartialEq)]
+pub struct ArgsDiff {
+    options: Vec<OptionDiff>,
+    /// The positional arguments of both argument lists, if they differ
+    positional: Option<(Vec<String>, Vec<String>)>,
+}
+
+/// The occurrences of an option that differ between two argument lists
+#[derive(Debug, Eq, PartialEq)]
+struct OptionDiff {
+    /// The canonical name of the option
+    name: OptName,
+    from: Vec<ParsedOpt>,
+    to: Vec<ParsedOpt>,
+    unordered: bool,
+    /// Whether the option is in an exclusive group of the vocabulary (so setting it would remove the other members)
+    exclusive: bool,
+}
+
+impl ArgsDiff {
+    pub fn new(from: &ParsedArgs, to: &ParsedArgs, vocabulary: &Vocabulary) -> Self {
+        let canonical = |name: &OptName| vocabulary.find(name).map_or(name.clone(), |option| option.names[0].clone());
+        let names = from.options.iter().chain(&to.options).map(|o| canonical(&o.name)).unique().collect_vec();
+
+        let options = names.into_iter().filter_map(|name| {
+            let occurrences = |args: &ParsedArgs| args.find_all(vocabulary.aliases(&name)).cloned().collect_vec();
+            let exclusive = vocabulary.exclusive_with(&name).next().is_some();
+            let option = OptionDiff {
+                from: occurrences(from),
+                to: occurrences(to),
+                unordered: vocabulary.is_unordered(&name),
+                exclusive,
+                name,
+            };
+            let (removed, added) = option.removed_and_added();
+            let changed = !removed.is_empty() || !added.is_empty();
+            changed.then_some(option)
+        }).collect();
+
+        let positional = (from.positional != to.positional)
+                .then(|| (from.positional.clone(), to.positional.clone()));
+        ArgsDiff { options, positional }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.options.is_empty() && self.positional.is_none()
+    }
+
+    /// Writes the differences as lines that start with `-` (removed), `+` (added) or `~` (changed), showing the
+    /// options as they are written in the dialect
+    pub fn write_report<W: Write>(&self, dialect: &Dialect, out: &mut W) -> Result<()> {
+        let format = |option: &ParsedOpt| -> Result<String> {
+            let args = ParsedArgs { options: vec![option.clone()], positional: vec![] };
+            Ok(dialect.generate_vec(args)?.iter().map(|arg| shell::quote(arg)).join(" "))
+        };
+
+        for option in &self.options {
+            if let ([from], [to]) = (option.from.as_slice(), option.to.as_slice()) {
+                writeln!(out, "~ {} -> {}", format(from)?, format(to)?)?;
+                continue;
+            }
+            let (removed, added) = option.removed_and_added();
+            for removed in removed {
+                writeln!(out, "- {}", format(removed)?)?;
+            }
+            for added in added {
+                writeln!(out, "+ {}", format(added)?)?;
+            }
+        }
+
+        if let Some((from, to)) = &self.positional {
+            let (removed, added) = sequence_diff(from, to, |a, b| a == b);
+            for removed in removed {
+                writeln!(out, "- positional: {}", shell::quote(removed))?;
+            }
+            for added in added {
+                writeln!(out, "+ positional: {}", shell::quote(added))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes a script (in the full syntax) that transforms the first argument list into the second one.
+    ///
+    /// Added options are placed after the other options, since the relative order of different options is ignored.
+    /// Options in exclusive groups are replaced with `remove` and `add`, since `set` would remove the other members of
+    /// the group.
+    pub fn write_script<W: Write>(&self, out: &mut W) -> Result<()> {
+        for option in &self.options {
+            let name = script_name(&option.name);
+            match (option.from.as_slice(), option.to.as_slice()) {
+                ([_], [to]) if !option.exclusive => writeln!(out, "set {}", script_option(to))?,
+                (_, []) => writeln!(out, "remove {name}")?,
+                (from, to) => {
+                    let (removed, added) = option.removed_and_added();
+                    let appended = if option.unordered {
+                        removed.is_empty()
+                    } else {
+                        from.len() <= to.len() && from.iter().zip(to).all(|(a, b)| same_values(a, b))
+                    };
+                    let added = if appended {
+                        added
+                    } else {
+                        writeln!(out, "remove {name}")?;
+                        to.iter().collect()
+                    };
+                    for added in added {
+                        writeln!(out, "add {}", script_option(added))?;
+                    }
+                }
+            }
+        }
+
+        if let Some((_, to)) = &self.positional {
+            writeln!(out, "pos.clear")?;
+            if !to.is_empty() {
+                writeln!(out, "pos.append {}", to.iter().map(|value| script_value(value)).join(" "))?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OptionDiff {
+    /// Returns the occurrences that are only in the first argument list, and the ones that are only in the second one
+    fn removed_and_added(&self) -> (Vec<&ParsedOpt>, Vec<&ParsedOpt>) {
+        if self.unordered {
+            multiset_diff(&self.from, &self.to, same_values)
+        } else {
+            sequence_diff(&self.from, &self.to, same_values)
+        }
+    }
+}
+
+fn same_values(a: &ParsedOpt, b: &ParsedOpt) -> bool {
+    a.values == b.values
+}
+
+/// Returns the items that are only in `a`, and the ones that are only in `b`, ignoring their order
+fn multiset_diff<'a, T, F>(a: &'a [T], b: &'a [T], eq: F) -> (Vec<&'a T>, Vec<&'a T>)
+    where F: Fn(&T, &T) -> bool
+{
+    let mut added = b.iter().collect_vec();
+    let removed = a.iter().filter(|item| match added.iter().position(|other| eq(item, other)) {
+        Some(index) => {
+            added.remove(index);
+            false
+        }
+        None => true,
+    }).collect();
+    (removed, added)
+}
+
+/// Returns the items that are only in `a`, and the ones that are only in `b`, keeping a longest common subsequence
+fn sequence_diff<'a, T, F>(a: &'a [T], b: &'a [T], eq: F) -> (Vec<&'a T>, Vec<&'a T>)
+    where F: Fn(&T, &T) -> bool
+{
+    // common[i][j] is the length of the longest common subsequence of a[i..] and b[j..]
+    let mut common = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+    for i in (0..a.len()).rev() {
+        for j in (0..b.len()).rev() {
+            common[i][j] = if eq(&a[i], &b[j]) {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let (mut removed, mut added) = (Vec::new(), Vec::new());
+    let (mut i, mut j) = (0, 0);
+    while i < a.len() && j < b.len() {
+        if eq(&a[i], &b[j]) {
+            (i, j) = (i + 1, j + 1);
+        } else if common[i + 1][j] >= common[i][j + 1] {
+            removed.push(&a[i]);
+            i += 1;
+        } else {
+            added.push(&b[j]);
+            j += 1;
+        }
+    }
+    removed.extend(&a[i..]);
+    added.extend(&b[j..]);
+    (removed, added)
+}
+
+/// Writes an option with its values as the arguments of `set` or `add`
+fn script_option(option: &ParsedOpt) -> String {
+    let name = script_name(&option.name);
+    if option.values.is_empty() {
+        name
+    } else {
+        format!("{name}={}", option.values.iter().map(|value| script_value(value)).join(" "))
+    }
+}
+
+/// Writes an option name for a script, with a `--` prefix for long names with a single character (which would be short
+/// names otherwise)
+fn script_name(name: &OptName) -> String {
+    let prefix = match name {
+        OptName::Long(long) if long.chars().count() == 1 => "--",
+        _ => "",
+    };
+    let name = name.unprefixed();
+    let is_ident = !name.starts_with('-') && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-');
+    if is_ident { format!("{prefix}{name}") } else { format!("{prefix}{}", script_value(&name)) }
+}
+
+/// Quotes a value for a script, so that it is not interpolated
+fn script_value(value: &str) -> String {
+    let value = value.replace("${", "$${");
+    if value.contains('\'') {
+        format!("\"{}\"", value.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        format!("'{value}'")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::sink;
+    use std::path::Path;
+    use clio::Input;
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::cli::{Dialect, DiffSpec};
+    use crate::commands::{CommandParser, Context, FullCommandParser};
+    use crate::opts::grammar::Gnu;
+    use crate::opts::parsed_args::ParsedArgs;
+    use crate::opts::vocabulary::Vocabulary;
+    use crate::util::testing::opts::sv;
+    use crate::util::testing::opts::parsed::{long, short};
+    use super::{diff, diff_status, ArgsDiff};
+
+    fn sample_args() -> (ParsedArgs, ParsedArgs) {
+        let from = ParsedArgs {
+            options: vec![
+                short!('O', "2"), short!('I', "a"), short!('D', "X"), short!('I', "b"), short!('D', "Y"),
+                long!("std", "c++17"), short!('g'), short!('v'),
+            ],
+            positional: sv!["main.c", "util.c"],
+        };
+        let to = ParsedArgs {
+            options: vec![
+                long!("verbose"), short!('O', "3"), short!('D', "Y"), short!('I', "b"), short!('I', "a"),
+                short!('D', "X"), short!('D', "${Z}"), long!("std", "c++20"), short!('o', "it's"), long!("O", "1"),
+            ],
+            positional: sv!["main.c", "lib.c"],
+        };
+        (from, to)
+    }
+
+    fn vocabulary() -> Result<Vocabulary> {
+        Vocabulary::parse("v|verbose\nD|define unordered")
+    }
+
+    #[test]
+    fn test_report() -> Result<()> {
+        let (from, to) = sample_args();
+        let diff = ArgsDiff::new(&from, &to, &vocabulary()?);
+        let mut out = Vec::new();
+        diff.write_report(&Dialect::Gnu(Gnu::default()), &mut out)?;
+        assert_eq!(String::from_utf8(out)?, "\
+~ -O 2 -> -O 3
+- -I a
++ -I a
++ -D \"\\${Z}\"
+~ --std=c++17 -> --std=c++20
+- -g
++ -o \"it's\"
++ --O=1
+- positional: util.c
++ positional: lib.c
+");
+        Ok(())
+    }
+
+    #[test]
+    fn test_equivalent() -> Result<()> {
+        // Only the order of the -I options matters, and --verbose and --define are aliases
+        let (from, _) = sample_args();
+        let to = ParsedArgs {
+            options: vec![
+                short!('D', "Y"), long!("verbose"), short!('g'), long!("std", "c++17"), short!('I', "a"),
+                short!('O', "2"), short!('I', "b"), long!("define", "X"),
+            ],
+            positional: sv!["main.c", "util.c"],
+        };
+        assert!(ArgsDiff::new(&from, &to, &vocabulary()?).is_empty());
+        assert!(!ArgsDiff::new(&from, &to, &Vocabulary::default()).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_script() -> Result<()> {
+        let (from, to) = sample_args();
+        let vocabulary = vocabulary()?;
+        let mut out = Vec::new();
+        ArgsDiff::new(&from, &to, &vocabulary).write_script(&mut out)?;
+        let script = String::from_utf8(out)?;
+        assert_eq!(script, "\
+set O='3'
+remove I
+add I='b'
+add I='a'
+add D='$${Z}'
+set std='c++20'
+remove g
+add o=\"it's\"
+add --O='1'
+pos.clear
+pos.append 'main.c' 'lib.c'
+");
+
+        let mut args = from;
+        let mut context = Context::new(Dialect::Gnu(Gnu::default()), vocabulary.clone());
+        for command in FullCommandParser::default().parse_from_script(&script)? {
+            command.apply(&mut args, &mut context)?;
+        }
+        assert!(ArgsDiff::new(&args, &to, &vocabulary).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_script_with_exclusive_groups() -> Result<()> {
+        let vocabulary = Vocabulary::parse("q|quiet\nv|verbose\nexclusive quiet verbose")?;
+        let from = ParsedArgs { options: vec![short!('q'), short!('v', "1"), short!('O', "1")], positional: sv![] };
+        let to = ParsedArgs { options: vec![short!('q'), short!('v', "2"), short!('O', "2")], positional: sv![] };
+
+        let mut out = Vec::new();
+        ArgsDiff::new(&from, &to, &vocabulary).write_script(&mut out)?;
+        let script = String::from_utf8(out)?;
+        assert_eq!(script, "remove v\nadd v='2'\nset O='2'\n");
+
+        let mut args = from;
+        let mut context = Context::new(Dialect::Gnu(Gnu::default()), vocabulary.clone());
+        for command in FullCommandParser::default().parse_from_script(&script)? {
+            command.apply(&mut args, &mut context)?;
+        }
+        assert!(ArgsDiff::new(&args, &to, &vocabulary).is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_status() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("oco-test-diff-status-{}", std::process::id()));
+        std::fs::create_dir_all(&dir)?;
+        let (from, to) = (dir.join("from.args"), dir.join("to.args"));
+        std::fs::write(&from, "-O2\nmain.c\n")?;
+        std::fs::write(&to, "-O3\nmain.c\n")?;
+
+        let status = |from: &Path, to: &Path| {
+            let spec = DiffSpec { from: Input::new(from)?, to: Input::new(to)?, cmdline: false, script: false };
+            let dialect = Dialect::Gnu(Gnu::default());
+            Ok::<_, eyre::Error>(diff_status(&diff(spec, &dialect, &Vocabulary::default(), &mut sink())))
+        };
+        assert_eq!(status(&from, &from)?, 0);
+        assert_eq!(status(&from, &to)?, 1);
+        assert_eq!(diff_status(&Err(eyre::eyre!("Failed to read input"))), 2);
+
+        std::fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_sequence_diff() {
+        let (a, b) = (['a', 'b', 'c', 'd'], ['b', 'x', 'd', 'a']);
+        assert_eq!(super::sequence_diff(&a, &b, |a, b| a == b), (vec![&'a', &'c'], vec![&'x', &'a']));
+        assert_eq!(super::multiset_diff(&a, &b, |a, b| a == b), (vec![&'c'], vec![&'x']));
+        assert_eq!(super::sequence_diff(&a, &[], |a, b| a == b), (a.iter().collect(), vec![]));
+    }
+}
@@ -3,53 +3,154 @@ mod opts;
 mod read_args;
 mod util;
 mod commands;
+mod query;
+mod diff;
+mod run;
+mod wrap;
 
 use clap::Parser;
+use clio::Input;
 use cli::Cli;
-use eyre::{Context, Result};
+use eyre::{Context as _, ContextCompat, Result};
+use itertools::Itertools;
 use opts::grammar::Grammar;
 use std::io::{BufReader, BufWriter, Write};
+use std::path::Path;
+use std::process::ExitCode;
 
-use crate::cli::{CommandSpec, Dialect};
-use crate::read_args::read_args;
-use crate::commands::{Command, CommandParser, CompactCommandParser, FullCommandParser};
+use crate::cli::{Action, CommandSpec, Dialect, InputSpec};
+use crate::read_args::{read_args, read_cmdline, read_cmdline_of_pid};
+use crate::commands::{
+    AssertionError, Command, CommandParser, CompactCommandParser, Context, FullCommandParser, ScriptHeader,
+};
+use crate::query::run_query;
+use crate::diff::{diff, diff_status};
+use crate::run::{exec, load_script, load_vocabulary};
 use crate::util::shell;
+use crate::wrap::wrap;
 
 
-fn main() {
-    color_eyre::install().expect("Failed to install color_eyre");
+fn main() -> Result<ExitCode> {
+    color_eyre::install()?;
 
     let cli = Cli::parse();
-    let args = read_args(BufReader::new(cli.input));
-    let mut parsed = <Dialect as Grammar>::parse(&cli.input_dialect, args)
-            .expect("Failed to parse input arguments");
+    cli.validate()?;
 
-    let commands = parse_commands(cli.commands)
-            .expect("Failed to parse commands");
+    if let Some(Action::Wrap(spec)) = &cli.action {
+        wrap(spec, &cli.input_dialect, &cli.output_dialect, cli.output)
+                .wrap_err("Failed to generate wrapper script")?;
+        return Ok(ExitCode::SUCCESS);
+    }
+
+    if let Some(Action::Diff(spec)) = cli.action {
+        let mut output = BufWriter::new(cli.output);
+        let result = cli.vocabulary.as_deref()
+                .map(load_vocabulary).transpose()
+                .and_then(|vocabulary| diff(spec, &cli.input_dialect, &vocabulary.unwrap_or_default(), &mut output))
+                .and_then(|differs| output.flush().wrap_err("Failed to write differences").map(|_| differs))
+                .wrap_err("Failed to compare argument lists");
+        // Errors have their own exit status, so they are reported here instead of being returned
+        if let Err(error) = &result {
+            eprintln!("Error: {error:?}");
+        }
+        return Ok(ExitCode::from(diff_status(&result)));
+    }
+
+    let mut vocabulary = cli.vocabulary.as_deref()
+            .map(load_vocabulary).transpose()?
+            .unwrap_or_default();
+
+    // When running a script, the arguments come after the script and the script header overrides the configuration
+    let (header, commands, input) = match cli.run {
+        Some(run) => {
+            let (script, args) = run.split_first().wrap_err("Missing script to run")?;
+            let (header, commands) = load_script(Path::new(script))?;
+            (header, commands, (None, args.to_vec()))
+        }
+        None => {
+            let input = read_input(cli.input, &cli.input_dialect)
+                    .wrap_err("Failed to read input arguments")?;
+            let commands = parse_commands(cli.commands)
+                    .wrap_err("Failed to parse commands")?;
+            (ScriptHeader::default(), commands, input)
+        }
+    };
+    let (program, args) = input;
+    let input_dialect = header.input_dialect.unwrap_or(cli.input_dialect);
+    let output_dialect = header.output_dialect.unwrap_or(cli.output_dialect);
+    vocabulary.extend(header.vocabulary)?;
+
+    let mut parsed = <Dialect as Grammar>::parse(&input_dialect, args.into_iter())
+            .wrap_err("Failed to parse input arguments")?;
 
+    let mut context = Context::new(input_dialect, vocabulary);
     for command in commands {
-        command.apply(&mut parsed)
+        if let Err(error) = command.apply(&mut parsed, &mut context) {
+            // Failed assertions are expected errors, so they are reported without any details
+            if let Some(failure) = error.downcast_ref::<AssertionError>() {
+                eprintln!("oco: {failure}");
+                return Ok(ExitCode::FAILURE);
+            }
+            return Err(error.wrap_err("Failed to apply command"));
+        }
     }
 
     let mut output = BufWriter::new(cli.output);
 
-    let mut first = true;
-    <Dialect as Grammar>::generate(&cli.output_dialect, parsed, |arg| {
-        let quoted_arg = shell::quote(arg.as_str());
-        if first {
-            first = false;
-            write!(output, "{quoted_arg}").expect("Failed to write output argument");
-        } else {
-            write!(output, " {quoted_arg}").expect("Failed to write output argument");
-        }
-    }).expect("Failed to generate output arguments");
+    if let Some(Action::Query(query)) = cli.action {
+        let found = run_query(&query, &parsed, &context.vocabulary, &mut output)
+                .wrap_err("Failed to write query output")?;
+        return Ok(if found { ExitCode::SUCCESS } else { ExitCode::FAILURE });
+    }
+
+    let generated = <Dialect as Grammar>::generate_vec(&output_dialect, parsed)
+            .wrap_err("Failed to generate output arguments")?;
+
+    if let Some(target) = header.target {
+        return exec(&target, generated);
+    }
+
+    let command_line = program.iter().chain(generated.iter())
+            .map(|arg| shell::quote(arg.as_str()))
+            .join(" ");
+
+    match cli.to_env {
+        Some(variable) => write!(output, "{variable}={}", shell::single_quote(&command_line)),
+        None => write!(output, "{command_line}"),
+    }.wrap_err("Failed to write output arguments")?;
+
+    Ok(ExitCode::SUCCESS)
+}
+
+/// Reads the input arguments, along with the program name if the input is a full command line.
+fn read_input(input: InputSpec, dialect: &Dialect) -> Result<(Option<String>, Vec<String>)> {
+    if let Some(variable) = input.from_env {
+        let value = std::env::var(&variable)
+                .ok().wrap_err_with(|| format!("Environment variable {variable} is not set or is not valid unicode"))?;
+        Ok((None, dialect.split(&value)?))
+    } else if let Some(pid) = input.from_pid {
+        let (program, args) = read_cmdline_of_pid(pid)?;
+        Ok((Some(program), args))
+    } else if let Some(cmdline_file) = input.from_cmdline_file {
+        let path = cmdline_file.path().to_string_lossy().into_owned();
+        let (program, args) = read_cmdline(cmdline_file)
+                .wrap_err_with(|| format!("Failed to read command line file: {path}"))?;
+        Ok((Some(program), args))
+    } else {
+        Ok((None, read_args(BufReader::new(input.input)).collect()))
+    }
 }
 
 fn parse_commands(commands: CommandSpec) -> Result<Vec<Command>> {
     if commands.full_script_syntax {
-        FullCommandParser::default().parse_commands(commands)
+        // Scripts read from stdin or pipes include other scripts relative to the working directory
+        let parser = match &commands.command_file {
+            Some(Input::File(path, _)) => FullCommandParser::for_script(path),
+            _ => FullCommandParser::default(),
+        };
+        parser.parse_commands(commands)
     } else {
-        CompactCommandParser::default().parse_commands(commands)
+        CompactCommandParser.parse_commands(commands)
     }
 }
 
@@ -1,3 +1,4 @@
+use std::fmt::{Display, Formatter};
 use crate::opts::grammar::Grammar;
 use crate::opts::parsed_args::{ParsedArgs, ParsedOpt};
 use derivative::Derivative;
@@ -64,16 +65,41 @@ pub enum PositionalArgumentsMode {
     Explicit,
 }
 
+impl Display for Gnu {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        // Boolean arguments are formatted as `name` (true) or `-name` (false)
+        let flag = |value: bool| if value { "" } else { "-" };
+        write!(
+            f, "gnu:positional={},{}explicit_positional,{}grouping,long_arg={}",
+            self.positional, flag(self.explicit_positional), flag(self.grouping), self.long_arg
+        )
+    }
+}
+
+impl Display for LongOptionArgumentFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LongOptionArgumentFormat::Equals => write!(f, "equals"),
+            LongOptionArgumentFormat::Space => write!(f, "space"),
+        }
+    }
+}
+
+impl Display for PositionalArgumentsMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PositionalArgumentsMode::Forbidden => write!(f, "forbidden"),
+            PositionalArgumentsMode::Free => write!(f, "free"),
+            PositionalArgumentsMode::Explicit => write!(f, "explicit"),
+        }
+    }
+}
+
 impl Grammar for Gnu {
     fn parse<I: IntoIterator<Item=String>>(&self, args: I) -> Result<ParsedArgs> {
         let mut state = GnuParserState::new(self);
 
-        let mut arg_iter = args.into_iter();
-
-        loop {
-            let Some(arg) = arg_iter.next() else {
-                break;
-            };
+        for arg in args {
             state.parse_next(arg)?;
         }
 
@@ -137,6 +163,13 @@ impl Grammar for Gnu {
 
         Ok(())
     }
+
+    fn split(&self, args: &str) -> Result<Vec<String>> {
+        match shlex::split(args) {
+            Some(args) => Ok(args),
+            None => bail!("Bad quoting in arguments: {args}"),
+        }
+    }
 }
 
 impl Gnu {
@@ -470,6 +503,15 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_split() -> Result<()> {
+        let grammar = Gnu::default();
+        assert_eq!(grammar.split("-a --foo='bar baz' \"quoted arg\"")?, vec!["-a", "--foo=bar baz", "quoted arg"]);
+        assert!(grammar.split("")?.is_empty());
+        assert_err_contains!(grammar.split("--foo='unterminated"), "Bad quoting");
+        Ok(())
+    }
+
     #[test]
     fn test_generate_explicit() -> Result<()> {
         let equals_grammar = Gnu::default();
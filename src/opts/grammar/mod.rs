@@ -5,7 +5,7 @@ use crate::opts::parsed_args::ParsedArgs;
 use crate::cli::Dialect;
 use eyre::Result;
 
-mod gnu;
+pub mod gnu;
 
 #[enum_dispatch]
 pub trait Grammar : Clone {
@@ -13,6 +13,10 @@ pub trait Grammar : Clone {
 
     fn generate<F : FnMut(String)>(&self, args: ParsedArgs, f: F) -> Result<()>;
 
+    /// Splits a single string holding multiple arguments (e.g. the value of an environment variable) into separate
+    /// arguments.
+    fn split(&self, args: &str) -> Result<Vec<String>>;
+
     fn generate_vec(&self, args: ParsedArgs) -> Result<Vec<String>> {
         let mut vec = Vec::new();
         self.generate(args, |s| vec.push(s))?;
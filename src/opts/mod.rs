@@ -1,2 +1,3 @@
 pub mod grammar;
 pub mod parsed_args;
+pub mod vocabulary;
@@ -1,6 +1,9 @@
 use std::borrow::Cow;
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
 use derivative::Derivative;
 use eyre::eyre;
+use crate::opts::vocabulary::Vocabulary;
 
 #[derive(Clone, Debug, Derivative, Eq, PartialEq)]
 #[derivative(Default)]
@@ -15,21 +18,79 @@ pub struct ParsedOpt {
     pub values: Vec<String>,
 }
 
-#[derive(Clone, Debug, Eq, PartialEq)]
+/// Decides which option names are treated as the same option (e.g. an option name and its aliases)
+pub trait OptMatcher {
+    fn matches(&self, name: &OptName) -> bool;
+}
+
+impl OptMatcher for OptName {
+    fn matches(&self, name: &OptName) -> bool {
+        self == name
+    }
+}
+
+impl<M: OptMatcher + ?Sized> OptMatcher for &M {
+    fn matches(&self, name: &OptName) -> bool {
+        (**self).matches(name)
+    }
+}
+
+/// Determines which options win when merging arguments from another source into existing arguments
+#[derive(Copy, Clone, Debug, Default, Eq, PartialEq)]
+pub enum MergePrecedence {
+    /// Merged options are dropped if an option with the same name already exists.
+    ///
+    /// The remaining merged arguments are placed before the existing ones.
+    #[default]
+    Existing,
+
+    /// Existing options are removed if a merged option with the same name exists.
+    ///
+    /// The merged arguments are placed after the existing ones.
+    Merged,
+}
+
+#[derive(Clone, Debug, Eq, Hash, PartialEq)]
 pub enum OptName {
     Long(String),
     Short(char),
 }
 
+impl OptName {
+    /// The name without any prefix, whether it is long or short
+    pub fn unprefixed(&self) -> Cow<'_, str> {
+        match self {
+            OptName::Long(name) => name.into(),
+            OptName::Short(name) => name.to_string().into(),
+        }
+    }
+}
+
 impl ParsedArgs {
-    pub fn remove_all_options(&mut self, name: OptName) {
-        self.options.retain(|o| o.name != name);
+    pub fn find_all<'a, M: OptMatcher + 'a>(&'a self, matcher: M) -> impl Iterator<Item=&'a ParsedOpt> + 'a {
+        self.options.iter().filter(move |o| matcher.matches(&o.name))
     }
 
-    pub fn update_last_option<F>(&mut self, name: OptName, update_values: F)
-        where F: FnOnce(&mut Vec<String>)
+    pub fn find_all_mut<'a, M>(&'a mut self, matcher: M) -> impl DoubleEndedIterator<Item=&'a mut ParsedOpt> + 'a
+        where M: OptMatcher + 'a
     {
-        match self.options.iter_mut().rfind(|o| o.name == name) {
+        self.options.iter_mut().filter(move |o| matcher.matches(&o.name))
+    }
+
+    pub fn find_last<M: OptMatcher>(&self, matcher: M) -> Option<&ParsedOpt> {
+        self.options.iter().rfind(|o| matcher.matches(&o.name))
+    }
+
+    pub fn remove_all_options<M: OptMatcher>(&mut self, matcher: M) {
+        self.options.retain(|o| !matcher.matches(&o.name));
+    }
+
+    /// Updates the values of the last option matching `matcher`, or adds a new option named `name` if none matches
+    pub fn update_last_option<M, F>(&mut self, matcher: M, name: OptName, update_values: F)
+        where M: OptMatcher,
+              F: FnOnce(&mut Vec<String>)
+    {
+        match self.options.iter_mut().rfind(|o| matcher.matches(&o.name)) {
             Some(option) => update_values(&mut option.values),
             None => {
                 let mut values = vec![];
@@ -39,12 +100,29 @@ impl ParsedArgs {
         }
     }
 
-    pub fn set_last_option(&mut self, name: OptName, values: Vec<String>) {
-        match self.options.iter_mut().rfind(|o| o.name == name) {
+    /// Sets the values of the last option matching `matcher`, or adds a new option named `name` if none matches
+    pub fn set_last_option<M: OptMatcher>(&mut self, matcher: M, name: OptName, values: Vec<String>) {
+        match self.options.iter_mut().rfind(|o| matcher.matches(&o.name)) {
             Some(option) => option.values = values,
             None => self.options.push(ParsedOpt { name, values })
         }
     }
+
+    /// Merges other arguments into these arguments, using the vocabulary to find options with the same name
+    pub fn merge(&mut self, mut other: ParsedArgs, precedence: MergePrecedence, vocabulary: &Vocabulary) {
+        match precedence {
+            MergePrecedence::Existing => {
+                other.options.retain(|o| self.find_last(vocabulary.aliases(&o.name)).is_none());
+                self.options.splice(0..0, other.options);
+                self.positional.splice(0..0, other.positional);
+            }
+            MergePrecedence::Merged => {
+                self.options.retain(|o| other.find_last(vocabulary.aliases(&o.name)).is_none());
+                self.options.append(&mut other.options);
+                self.positional.append(&mut other.positional);
+            }
+        }
+    }
 }
 
 impl<'a> TryFrom<Cow<'a, str>> for OptName {
@@ -70,6 +148,32 @@ impl TryFrom<&str> for OptName {
     }
 }
 
+/// Formats the option name as it is written in scripts (without any dialect-specific prefix, except for long names
+/// with a single character, which are written with `--` to tell them apart from short names)
+impl Display for OptName {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OptName::Long(name) if name.chars().count() == 1 => write!(f, "--{name}"),
+            OptName::Long(name) => write!(f, "{name}"),
+            OptName::Short(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// Parses an option name as it is formatted (where a `--` prefix makes the name long, e.g. `--O`). All option names
+/// written by users (in scripts, queries, references and vocabularies) are parsed this way.
+impl FromStr for OptName {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.strip_prefix("--") {
+            Some("") => Err(eyre!("Option name cannot be empty")),
+            Some(name) => Ok(OptName::Long(name.to_string())),
+            None => Self::try_from(s),
+        }
+    }
+}
+
 impl TryFrom<String> for OptName {
     type Error = eyre::Error;
 
@@ -81,3 +185,76 @@ impl TryFrom<String> for OptName {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use similar_asserts::assert_eq;
+    use crate::opts::parsed_args::{MergePrecedence, OptName, ParsedArgs};
+    use crate::opts::vocabulary::Vocabulary;
+    use crate::util::testing::opts::sv;
+    use crate::util::testing::opts::parsed::{long, short};
+
+    fn existing_and_merged() -> (ParsedArgs, ParsedArgs) {
+        let existing = ParsedArgs {
+            options: vec![long!("color", "never"), short!('v'), long!("color", "auto")],
+            positional: sv!["file1"],
+        };
+        let merged = ParsedArgs {
+            options: vec![short!('i'), long!("color", "always")],
+            positional: sv!["file2"],
+        };
+        (existing, merged)
+    }
+
+    #[test]
+    fn test_name_format() -> eyre::Result<()> {
+        for (name, formatted) in [
+            (OptName::Short('O'), "O"),
+            (OptName::Long("O".to_string()), "--O"),
+            (OptName::Long("output".to_string()), "output"),
+        ] {
+            assert_eq!(name.to_string(), formatted);
+            assert_eq!(formatted.parse::<OptName>()?, name);
+        }
+        assert_eq!("--output".parse::<OptName>()?, OptName::Long("output".to_string()));
+        assert!("--".parse::<OptName>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_existing_precedence() {
+        let (mut args, merged) = existing_and_merged();
+        args.merge(merged, MergePrecedence::Existing, &Vocabulary::default());
+        assert_eq!(args, ParsedArgs {
+            options: vec![short!('i'), long!("color", "never"), short!('v'), long!("color", "auto")],
+            positional: sv!["file2", "file1"],
+        });
+    }
+
+    #[test]
+    fn test_merge_merged_precedence() {
+        let (mut args, merged) = existing_and_merged();
+        args.merge(merged, MergePrecedence::Merged, &Vocabulary::default());
+        assert_eq!(args, ParsedArgs {
+            options: vec![short!('v'), short!('i'), long!("color", "always")],
+            positional: sv!["file1", "file2"],
+        });
+    }
+
+    #[test]
+    fn test_merge_with_aliases() -> eyre::Result<()> {
+        let vocabulary = Vocabulary::parse("c|color")?;
+
+        let (mut args, merged) = existing_and_merged();
+        args.options.retain(|o| o.name != long!("color").name);
+        args.options.push(short!('c', "never"));
+        args.merge(merged, MergePrecedence::Existing, &vocabulary);
+        assert_eq!(args.options, vec![short!('i'), short!('v'), short!('c', "never")]);
+
+        let (mut args, merged) = existing_and_merged();
+        args.options.push(short!('c', "never"));
+        args.merge(merged, MergePrecedence::Merged, &vocabulary);
+        assert_eq!(args.options, vec![short!('v'), short!('i'), long!("color", "always")]);
+        Ok(())
+    }
+}
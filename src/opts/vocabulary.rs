@@ -0,0 +1,294 @@
+use eyre::{bail, eyre, Context, Result};
+use itertools::Itertools;
+
+use crate::opts::parsed_args::{OptMatcher, OptName};
+
+/// Describes the options known to a program.
+///
+/// A vocabulary is written as one option per line, with all the names of the option separated by `|`
+/// (e.g. `v|verbose`), optionally followed by the form of its values: `repeated`, `variadic` or `delimited=DELIM`
+/// (e.g. `I|include repeated`, see [ValueForm]), and by `unordered` if the order of its occurrences is irrelevant.
+/// Mutually exclusive options are declared in lines starting with `exclusive`, followed by the names of the options
+/// separated by spaces (e.g. `exclusive quiet verbose`). Empty lines and comments starting with `#` are ignored.
+#[derive(Clone, Debug, Default, Eq, PartialEq)]
+pub struct Vocabulary {
+    options: Vec<VocabOption>,
+
+    /// Groups of options where setting one of them removes the others
+    exclusive_groups: Vec<Vec<OptName>>,
+}
+
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct VocabOption {
+    /// All the names of the option, starting with its canonical name
+    pub names: Vec<OptName>,
+    /// How the option takes multiple values, if declared
+    pub form: Option<ValueForm>,
+    /// Whether the order of the occurrences of the option is irrelevant (e.g. for `-D` macro definitions)
+    pub unordered: bool,
+}
+
+/// The ways an option can take multiple values (used by `explode` and `implode`)
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum ValueForm {
+    /// One value per occurrence (e.g. `-I a -I b`)
+    Repeated,
+    /// A single value with the items separated by a delimiter (e.g. `--features=a,b`)
+    Delimited(String),
+    /// A single occurrence with multiple values (e.g. `--files a b`)
+    Variadic,
+}
+
+impl ValueForm {
+    fn parse(form: &str) -> Result<Self> {
+        match form {
+            "repeated" => Ok(ValueForm::Repeated),
+            "variadic" => Ok(ValueForm::Variadic),
+            "delimited=" => bail!("The delimiter of a delimited option cannot be empty"),
+            _ => form.strip_prefix("delimited=")
+                    .map(|delimiter| ValueForm::Delimited(delimiter.to_string()))
+                    .ok_or_else(|| eyre!("Unknown option attribute: '{form}'")),
+        }
+    }
+}
+
+impl Vocabulary {
+    pub fn parse(source: &str) -> Result<Self> {
+        let mut vocabulary = Vocabulary::default();
+        for (line_number, line) in source.lines().enumerate() {
+            let line = line.split_once('#').map_or(line, |(line, _comment)| line).trim();
+            if line.is_empty() {
+                continue;
+            }
+            match line.strip_prefix("exclusive ") {
+                Some(group) => vocabulary.add_exclusive_group(group),
+                None => vocabulary.add_option(line),
+            }.wrap_err_with(|| format!("Bad vocabulary definition in line {}", line_number + 1))?;
+        }
+        Ok(vocabulary)
+    }
+
+    /// Adds an option from its definition (e.g. `v|verbose` or `D|define repeated unordered`)
+    pub fn add_option(&mut self, definition: &str) -> Result<()> {
+        // Names can be separated by spaces around the `|`, so the attributes start at the first word that is not joined
+        // to the previous one by a `|`
+        let words = definition.split_whitespace().collect_vec();
+        let names_end = (1..words.len())
+                .find(|&i| !words[i - 1].ends_with('|') && !words[i].starts_with('|'))
+                .unwrap_or(words.len());
+
+        let (mut form, mut unordered) = (None, false);
+        for &attribute in &words[names_end..] {
+            match attribute {
+                "unordered" => unordered = true,
+                _ if form.is_some() => bail!("Option has more than one form of values: '{definition}'"),
+                _ => form = Some(ValueForm::parse(attribute)?),
+            }
+        }
+
+        let names: Vec<OptName> = words[..names_end].concat().split('|').map(str::parse).try_collect()?;
+
+        if let Some(known) = names.iter().find(|name| self.find(name).is_some()) {
+            bail!("Option '{known}' is already defined");
+        }
+        if !names.iter().all_unique() {
+            bail!("Option names must be unique: '{definition}'");
+        }
+
+        self.options.push(VocabOption { names, form, unordered });
+        Ok(())
+    }
+
+    /// Adds a group of mutually exclusive options from its definition (e.g. `quiet verbose`)
+    pub fn add_exclusive_group(&mut self, definition: &str) -> Result<()> {
+        let names = definition.split_whitespace().map(str::parse).try_collect()?;
+        self.add_exclusive_names(names)
+    }
+
+    pub fn add_exclusive_names(&mut self, names: Vec<OptName>) -> Result<()> {
+        if names.len() < 2 {
+            bail!("An exclusive group needs at least two options");
+        }
+        if !names.iter().all_unique() {
+            bail!("Exclusive options must be unique: {}", names.iter().join(" "));
+        }
+        self.exclusive_groups.push(names);
+        Ok(())
+    }
+
+    pub fn extend(&mut self, other: Vocabulary) -> Result<()> {
+        for option in other.options {
+            if let Some(known) = option.names.iter().find(|name| self.find(name).is_some()) {
+                bail!("Option '{known}' is already defined");
+            }
+            self.options.push(option);
+        }
+        self.exclusive_groups.extend(other.exclusive_groups);
+        Ok(())
+    }
+
+    pub fn find(&self, name: &OptName) -> Option<&VocabOption> {
+        self.options.iter().find(|option| option.names.contains(name))
+    }
+
+    /// Returns the declared form of the values of an option
+    pub fn value_form(&self, name: &OptName) -> Option<&ValueForm> {
+        self.find(name).and_then(|option| option.form.as_ref())
+    }
+
+    pub fn is_unordered(&self, name: &OptName) -> bool {
+        self.find(name).is_some_and(|option| option.unordered)
+    }
+
+    /// Returns the index of the option with the given name, in the order the options were defined
+    pub fn index_of(&self, name: &OptName) -> Option<usize> {
+        self.options.iter().position(|option| option.names.contains(name))
+    }
+
+    pub fn exclusive_groups(&self) -> &[Vec<OptName>] {
+        &self.exclusive_groups
+    }
+
+    /// Returns the options that are mutually exclusive with an option (not including the option, or its aliases)
+    pub fn exclusive_with<'a>(&'a self, name: &'a OptName) -> impl Iterator<Item=&'a OptName> + 'a {
+        let matcher = self.aliases(name);
+        self.exclusive_groups.iter()
+                .filter(move |group| group.iter().any(|member| matcher.matches(member)))
+                .flatten()
+                .filter(move |member| !matcher.matches(member))
+    }
+
+    /// Returns a matcher for the given option name, and all of its aliases
+    pub fn aliases<'a>(&'a self, name: &'a OptName) -> Aliases<'a> {
+        Aliases { name, option: self.find(name) }
+    }
+}
+
+/// Matches an option name along with all of its aliases in a vocabulary
+#[derive(Copy, Clone, Debug)]
+pub struct Aliases<'a> {
+    name: &'a OptName,
+    option: Option<&'a VocabOption>,
+}
+
+impl OptMatcher for Aliases<'_> {
+    fn matches(&self, name: &OptName) -> bool {
+        match self.option {
+            Some(option) => option.names.contains(name),
+            None => self.name == name,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::opts::parsed_args::OptMatcher;
+    use crate::opts::vocabulary::{ValueForm, VocabOption, Vocabulary};
+    use crate::util::testing::assert_err_contains;
+    use crate::util::testing::opts::name::{long, short};
+
+    #[test]
+    fn test_parse() -> Result<()> {
+        let vocabulary = Vocabulary::parse("
+            # Comments and empty lines are ignored
+            a|all
+            v | verbose | loud # Trailing comment
+            color
+        ")?;
+        assert_eq!(vocabulary.options, vec![
+            VocabOption { names: vec![short!('a'), long!("all")], form: None, unordered: false },
+            VocabOption { names: vec![short!('v'), long!("verbose"), long!("loud")], form: None, unordered: false },
+            VocabOption { names: vec![long!("color")], form: None, unordered: false },
+        ]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_forms() -> Result<()> {
+        let vocabulary = Vocabulary::parse("
+            I|include repeated
+            features  delimited=,  # Comment
+            files variadic
+            a | repeated
+            D |define repeated unordered
+            color
+        ")?;
+        assert_eq!(vocabulary.value_form(&short!('I')), Some(&ValueForm::Repeated));
+        assert_eq!(vocabulary.value_form(&long!("features")), Some(&ValueForm::Delimited(",".to_string())));
+        assert_eq!(vocabulary.value_form(&long!("files")), Some(&ValueForm::Variadic));
+        assert_eq!(vocabulary.value_form(&long!("repeated")), None);
+        assert_eq!(vocabulary.value_form(&long!("color")), None);
+        assert_eq!(vocabulary.value_form(&long!("other")), None);
+        assert_eq!(vocabulary.value_form(&long!("define")), Some(&ValueForm::Repeated));
+        assert!(vocabulary.is_unordered(&short!('D')));
+        assert!(!vocabulary.is_unordered(&short!('I')));
+        assert!(!vocabulary.is_unordered(&long!("other")));
+
+        assert_err_contains!(Vocabulary::parse("features delimited=").map_err(|e| e.root_cause().to_string()),
+                             "cannot be empty");
+        assert_err_contains!(Vocabulary::parse("features joined").map_err(|e| e.root_cause().to_string()),
+                             "Unknown option attribute: 'joined'");
+        assert_err_contains!(Vocabulary::parse("features repeated variadic").map_err(|e| e.root_cause().to_string()),
+                             "more than one form");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert_err_contains!(Vocabulary::parse("a|all\nall"), "line 2");
+        assert_err_contains!(Vocabulary::parse("a|all\nall").map_err(|e| e.root_cause().to_string()),
+                             "already defined");
+        assert_err_contains!(Vocabulary::parse("a||all").map_err(|e| e.root_cause().to_string()),
+                             "cannot be empty");
+        assert_err_contains!(Vocabulary::parse("a|a").map_err(|e| e.root_cause().to_string()),
+                             "must be unique");
+    }
+
+    #[test]
+    fn test_exclusive_groups() -> Result<()> {
+        let mut vocabulary = Vocabulary::parse("
+            q|quiet
+            v|verbose
+            exclusive quiet verbose debug
+            exclusive  color no-color  # Comment
+        ")?;
+        vocabulary.add_exclusive_group("debug trace")?;
+        assert_eq!(vocabulary.exclusive_groups(), &[
+            vec![long!("quiet"), long!("verbose"), long!("debug")],
+            vec![long!("color"), long!("no-color")],
+            vec![long!("debug"), long!("trace")],
+        ]);
+        assert_eq!(vocabulary.exclusive_with(&short!('q')).collect::<Vec<_>>(),
+                   vec![&long!("verbose"), &long!("debug")]);
+        assert_eq!(vocabulary.exclusive_with(&long!("debug")).collect::<Vec<_>>(),
+                   vec![&long!("quiet"), &long!("verbose"), &long!("trace")]);
+        assert_eq!(vocabulary.exclusive_with(&long!("other")).count(), 0);
+
+        assert_err_contains!(Vocabulary::parse("exclusive quiet").map_err(|e| e.root_cause().to_string()),
+                             "at least two options");
+        assert_err_contains!(Vocabulary::parse("exclusive a a").map_err(|e| e.root_cause().to_string()),
+                             "must be unique");
+        Ok(())
+    }
+
+    #[test]
+    fn test_aliases() -> Result<()> {
+        let vocabulary = Vocabulary::parse("v|verbose")?;
+        let (v, verbose, other) = (short!('v'), long!("verbose"), long!("other"));
+
+        assert!(vocabulary.aliases(&v).matches(&verbose));
+        assert!(vocabulary.aliases(&verbose).matches(&v));
+        assert!(!vocabulary.aliases(&verbose).matches(&other));
+        assert!(vocabulary.aliases(&other).matches(&other));
+        assert!(!vocabulary.aliases(&other).matches(&v));
+
+        // Single-character long names are written with `--`
+        let vocabulary = Vocabulary::parse("level|--O\nexclusive --O q")?;
+        assert!(vocabulary.aliases(&long!("level")).matches(&long!("O")));
+        assert!(!vocabulary.aliases(&long!("level")).matches(&short!('O')));
+        assert_eq!(vocabulary.exclusive_with(&short!('q')).collect::<Vec<_>>(), vec![&long!("O")]);
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+use std::io::Write;
+use eyre::Result;
+
+use crate::cli::Query;
+use crate::opts::parsed_args::ParsedArgs;
+use crate::opts::vocabulary::Vocabulary;
+
+/// Runs a query against the parsed arguments, writing its output to `out`.
+///
+/// Options are looked up by all of their names in the vocabulary.
+/// Returns `false` if the query did not find what it was looking for, so the caller can report it through the exit
+/// status.
+pub fn run_query<W: Write>(query: &Query, args: &ParsedArgs, vocabulary: &Vocabulary, out: &mut W) -> Result<bool> {
+    match query {
+        Query::Get { all: false, name } => {
+            let Some(option) = args.find_last(vocabulary.aliases(name)) else {
+                return Ok(false);
+            };
+            for value in &option.values {
+                writeln!(out, "{value}")?;
+            }
+            Ok(true)
+        }
+        Query::Get { all: true, name } => {
+            let mut found = false;
+            for option in args.find_all(vocabulary.aliases(name)) {
+                found = true;
+                for value in &option.values {
+                    writeln!(out, "{value}")?;
+                }
+            }
+            Ok(found)
+        }
+        Query::Has { name } => {
+            Ok(args.find_last(vocabulary.aliases(name)).is_some())
+        }
+        Query::Count { name } => {
+            writeln!(out, "{}", args.find_all(vocabulary.aliases(name)).count())?;
+            Ok(true)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::cli::Query;
+    use crate::opts::parsed_args::ParsedArgs;
+    use crate::opts::vocabulary::Vocabulary;
+    use crate::util::testing::opts::sv;
+    use crate::util::testing::opts::parsed::{long, short};
+    use crate::util::testing::opts::name;
+
+    fn sample_args() -> ParsedArgs {
+        ParsedArgs {
+            options: vec![
+                long!("output", "first.o"),
+                short!('v'),
+                short!('I', "include"),
+                long!("output", "second.o", "extra"),
+                short!('I', "/usr/include"),
+            ],
+            positional: sv!["main.c"],
+        }
+    }
+
+    fn query_output(query: Query) -> Result<(bool, String)> {
+        query_output_with_vocabulary(query, &Vocabulary::default())
+    }
+
+    fn query_output_with_vocabulary(query: Query, vocabulary: &Vocabulary) -> Result<(bool, String)> {
+        let mut out = Vec::new();
+        let found = super::run_query(&query, &sample_args(), vocabulary, &mut out)?;
+        Ok((found, String::from_utf8(out)?))
+    }
+
+    #[test]
+    fn test_get() -> Result<()> {
+        assert_eq!(query_output(Query::Get { all: false, name: name::long!("output") })?,
+                   (true, "second.o\nextra\n".to_string()));
+        assert_eq!(query_output(Query::Get { all: true, name: name::short!('I') })?,
+                   (true, "include\n/usr/include\n".to_string()));
+
+        // Flags are found, but have no values
+        assert_eq!(query_output(Query::Get { all: false, name: name::short!('v') })?,
+                   (true, "".to_string()));
+
+        // Missing options
+        assert_eq!(query_output(Query::Get { all: false, name: name::long!("missing") })?,
+                   (false, "".to_string()));
+        assert_eq!(query_output(Query::Get { all: true, name: name::long!("missing") })?,
+                   (false, "".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_has() -> Result<()> {
+        assert_eq!(query_output(Query::Has { name: name::short!('v') })?, (true, "".to_string()));
+        assert_eq!(query_output(Query::Has { name: name::long!("v") })?, (false, "".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_count() -> Result<()> {
+        assert_eq!(query_output(Query::Count { name: name::long!("output") })?, (true, "2\n".to_string()));
+        assert_eq!(query_output(Query::Count { name: name::long!("missing") })?, (true, "0\n".to_string()));
+        Ok(())
+    }
+
+    #[test]
+    fn test_query_with_aliases() -> Result<()> {
+        let vocabulary = Vocabulary::parse("o|output\nI|include")?;
+        assert_eq!(query_output_with_vocabulary(Query::Get { all: false, name: name::short!('o') }, &vocabulary)?,
+                   (true, "second.o\nextra\n".to_string()));
+        assert_eq!(query_output_with_vocabulary(Query::Count { name: name::long!("include") }, &vocabulary)?,
+                   (true, "2\n".to_string()));
+        Ok(())
+    }
+}
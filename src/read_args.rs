@@ -1,4 +1,6 @@
-use std::io::{BufRead};
+use std::fs::File;
+use std::io::{BufRead, Read};
+use eyre::{bail, Context, Result};
 
 
 
@@ -9,3 +11,82 @@ pub fn read_args<B: BufRead>(b: B) -> impl Iterator<Item=String> {
         shlex::split(l.as_str()).expect("Bad command line input")
     })
 }
+
+/// Reads a NUL-separated command line (in the format of `/proc/<pid>/cmdline`), and splits it into the program name
+/// (`argv[0]`) and the rest of the arguments.
+pub fn read_cmdline<R: Read>(mut r: R) -> Result<(String, Vec<String>)> {
+    let mut cmdline = Vec::new();
+    r.read_to_end(&mut cmdline).wrap_err("Cannot read command line")?;
+
+    // Every argument is terminated by a NUL character, including the last one
+    if cmdline.last() == Some(&0) {
+        cmdline.pop();
+    }
+    if cmdline.is_empty() {
+        bail!("Command line is empty");
+    }
+
+    let mut args = cmdline
+            .split(|&b| b == 0)
+            .map(|arg| String::from_utf8(arg.to_vec()).wrap_err("Command line is not valid UTF-8"));
+    let program = args.next().expect("split() always returns at least one item")?;
+    Ok((program, args.collect::<Result<_>>()?))
+}
+
+/// Reads the command line of a running process from `/proc/<pid>/cmdline`.
+pub fn read_cmdline_of_pid(pid: u32) -> Result<(String, Vec<String>)> {
+    let path = format!("/proc/{pid}/cmdline");
+    let file = File::open(&path)
+            .wrap_err_with(|| format!("Cannot open command line of process {pid} ({path})"))?;
+    read_cmdline(file)
+            .wrap_err_with(|| format!("Cannot read command line of process {pid}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::util::testing::assert_err_contains;
+    use crate::util::testing::opts::sv;
+
+    #[test]
+    fn test_read_cmdline() -> Result<()> {
+        let (program, args) = super::read_cmdline(&b"/usr/bin/ls\0-l\0--color=auto\0file name\0"[..])?;
+        assert_eq!(program, "/usr/bin/ls");
+        assert_eq!(args, sv!["-l", "--color=auto", "file name"]);
+
+        // No trailing NUL
+        let (program, args) = super::read_cmdline(&b"ls\0-a"[..])?;
+        assert_eq!(program, "ls");
+        assert_eq!(args, sv!["-a"]);
+
+        // Empty arguments are preserved
+        let (program, args) = super::read_cmdline(&b"ls\0\0-a\0"[..])?;
+        assert_eq!(program, "ls");
+        assert_eq!(args, sv!["", "-a"]);
+
+        // Program only
+        let (program, args) = super::read_cmdline(&b"ls\0"[..])?;
+        assert_eq!(program, "ls");
+        assert!(args.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_bad_cmdline() {
+        assert_err_contains!(super::read_cmdline(&b""[..]), "empty");
+        assert_err_contains!(super::read_cmdline(&b"\0"[..]), "empty");
+        assert_err_contains!(super::read_cmdline(&b"ls\0\xff\0"[..]), "UTF-8");
+    }
+
+    #[test]
+    #[cfg(target_os = "linux")]
+    fn test_read_cmdline_of_current_process() -> Result<()> {
+        let (program, args) = super::read_cmdline_of_pid(std::process::id())?;
+        let mut expected = std::env::args();
+        assert_eq!(Some(program), expected.next());
+        assert_eq!(args, expected.collect::<Vec<_>>());
+        Ok(())
+    }
+}
@@ -0,0 +1,56 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::ExitCode;
+use eyre::{Context, Result};
+
+use crate::commands::{Command, FullCommandParser, ScriptHeader};
+use crate::opts::vocabulary::Vocabulary;
+use crate::wrap::resolve_target;
+
+/// Loads a script that is run as a program (see `oco --run`).
+///
+/// Vocabulary files are loaded relative to the script, and the target program is resolved without ever resolving to the
+/// script itself.
+pub fn load_script(path: &Path) -> Result<(ScriptHeader, Vec<Command>)> {
+    let script = fs::read_to_string(path)
+            .wrap_err_with(|| format!("Failed to read script file: {}", path.display()))?;
+    let (mut header, commands) = FullCommandParser::for_script(path).parse_script(&script)
+            .wrap_err_with(|| format!("Failed to parse script file: {}", path.display()))?;
+
+    let script_dir = path.parent().unwrap_or(Path::new(""));
+    for vocabulary_file in header.vocabulary_files.drain(..) {
+        let vocabulary = load_vocabulary(&script_dir.join(vocabulary_file))?;
+        header.vocabulary.extend(vocabulary)?;
+    }
+
+    if let Some(target) = &header.target {
+        let target = target.to_string_lossy();
+        header.target = Some(resolve_target(&target, &env::var_os("PATH").unwrap_or_default(), Some(path))?);
+    }
+
+    Ok((header, commands))
+}
+
+pub fn load_vocabulary(path: &Path) -> Result<Vocabulary> {
+    fs::read_to_string(path)
+            .map_err(eyre::Error::from)
+            .and_then(|source| Vocabulary::parse(&source))
+            .wrap_err_with(|| format!("Failed to load vocabulary file: {}", path.display()))
+}
+
+/// Replaces the current process with the target program
+#[cfg(unix)]
+pub fn exec(target: &Path, args: Vec<String>) -> Result<ExitCode> {
+    use std::os::unix::process::CommandExt;
+    let error = std::process::Command::new(target).args(args).exec();
+    Err(error).wrap_err_with(|| format!("Failed to run {}", target.display()))
+}
+
+/// Runs the target program, and exits with its exit code
+#[cfg(not(unix))]
+pub fn exec(target: &Path, args: Vec<String>) -> Result<ExitCode> {
+    let status = std::process::Command::new(target).args(args).status()
+            .wrap_err_with(|| format!("Failed to run {}", target.display()))?;
+    Ok(status.code().map_or(ExitCode::FAILURE, |code| ExitCode::from(code as u8)))
+}
@@ -3,4 +3,5 @@ pub mod testing;
 pub mod str;
 pub mod vec;
 pub mod shell;
+#[allow(dead_code)]
 pub mod iter;
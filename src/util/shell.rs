@@ -1,6 +1,6 @@
 use std::borrow::Cow;
 
-pub fn quote(in_str: &str) -> Cow<str> {
+pub fn quote(in_str: &str) -> Cow<'_, str> {
     if in_str.is_empty() {
         "\"\"".into()
     } else if in_str.bytes().any(|c| should_be_quoted(c as char)) {
@@ -22,6 +22,11 @@ pub fn quote(in_str: &str) -> Cow<str> {
     }
 }
 
+/// Quotes a string with single quotes, so it is never interpolated by the shell.
+pub fn single_quote(in_str: &str) -> String {
+    format!("'{}'", in_str.replace('\'', "'\\''"))
+}
+
 fn should_be_quoted(c: char) -> bool {
     matches!(c,
         '|' | '&' | ';' | '<' | '>' | '(' | ')' | '$' | '*' |
@@ -1,8 +1,9 @@
+#[allow(dead_code)]
 pub trait SplitExt: Sized {
     fn split_kv(self) -> (Self, Option<Self>);
 }
 
-impl <'a> SplitExt for &'a str {
+impl SplitExt for &str {
     fn split_kv(self) -> (Self, Option<Self>) {
         match self.split_once('=') {
             None => (self, None),
@@ -1,6 +1,7 @@
 pub trait PushExt<T> {
     fn ensure_first_or_default(&mut self) -> &mut T
         where T: Default;
+    #[allow(dead_code)]
     fn push_and_borrow(&mut self, value: T) -> &mut T;
 }
 
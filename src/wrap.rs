@@ -0,0 +1,181 @@
+use std::{env, fs};
+use std::ffi::OsStr;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use clio::Output;
+use eyre::{bail, Context, ContextCompat, Result};
+
+use crate::cli::{Dialect, WrapSpec};
+use crate::commands::{CommandParser, CompactCommandParser, FullCommandParser};
+use crate::util::shell::single_quote;
+
+/// Writes the wrapper script to the output, making it executable if the output is a file.
+pub fn wrap(spec: &WrapSpec, input_dialect: &Dialect, output_dialect: &Dialect, mut output: Output) -> Result<()> {
+    let wrapper_path = match &output {
+        Output::File(path, _) => Some(PathBuf::from(path)),
+        _ => None,
+    };
+
+    write_wrapper(spec, input_dialect, output_dialect, wrapper_path.as_deref(), &mut output)?;
+
+    #[cfg(unix)]
+    if let Output::File(path, file) = &output {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o755))
+                .wrap_err_with(|| format!("Failed to make wrapper script executable: {}", path.to_string_lossy()))?;
+    }
+
+    output.finish().wrap_err("Failed to write wrapper script")
+}
+
+/// Writes a shell script that processes its arguments with the commands from the script file, and then replaces itself
+/// with the target program.
+///
+/// `wrapper_path` is the path the wrapper script is written to (if known), and is used for avoiding recursion.
+pub fn write_wrapper<W: Write>(
+    spec: &WrapSpec,
+    input_dialect: &Dialect,
+    output_dialect: &Dialect,
+    wrapper_path: Option<&Path>,
+    out: &mut W,
+) -> Result<()> {
+    let script_path = spec.script.display();
+    let script = fs::read_to_string(&spec.script)
+            .wrap_err_with(|| format!("Failed to read script file: {script_path}"))?;
+
+    // Fail early, instead of generating a wrapper that fails on every invocation
+    if spec.full_script_syntax {
+        FullCommandParser::default().parse_from_script(&script)
+    } else {
+        CompactCommandParser.parse_from_script(&script)
+    }.wrap_err_with(|| format!("Failed to parse commands from script file: {script_path}"))?;
+
+    let target = resolve_target(&spec.target, &env::var_os("PATH").unwrap_or_default(), wrapper_path)?;
+    let target = target.to_str()
+            .wrap_err_with(|| format!("Target path is not valid unicode: {}", target.display()))?;
+
+    let script_syntax_flag = if spec.full_script_syntax { "-l " } else { "" };
+    write!(out, "\
+#!/bin/sh
+# Generated by oco: processes the arguments with oco, and then runs {target}
+target={target_arg}
+args=$(printf '%s\\0' \"$target\" \"$@\" | {oco} -I {input_dialect} -O {output_dialect} \\
+        --from-cmdline-file - {script_syntax_flag}-c {script}) || exit
+eval \"exec $args\"
+",
+        target_arg = single_quote(target),
+        oco = single_quote(&spec.oco),
+        input_dialect = single_quote(&input_dialect.to_string()),
+        output_dialect = single_quote(&output_dialect.to_string()),
+        script = single_quote(&script),
+    )?;
+
+    Ok(())
+}
+
+/// Resolves the target program into a path, looking it up in `path_var` (PATH) if it is only a name.
+///
+/// Resolving to the wrapper itself is an error, and PATH lookup skips it.
+pub fn resolve_target(target: &str, path_var: &OsStr, wrapper_path: Option<&Path>) -> Result<PathBuf> {
+    if target.contains('/') {
+        let path = std::path::absolute(target)
+                .wrap_err_with(|| format!("Invalid target path: {target}"))?;
+        if is_same_file(&path, wrapper_path) {
+            bail!("Target program {target} is the wrapper script itself");
+        }
+        return Ok(path);
+    }
+
+    env::split_paths(path_var)
+            .map(|dir| dir.join(target))
+            .filter(|candidate| candidate.is_absolute())
+            .find(|candidate| candidate.is_file() && !is_same_file(candidate, wrapper_path))
+            .wrap_err_with(|| format!("Target program {target} was not found in PATH"))
+}
+
+fn is_same_file(path: &Path, other: Option<&Path>) -> bool {
+    let Some(other) = other else {
+        return false;
+    };
+    match (path.canonicalize(), other.canonicalize()) {
+        (Ok(path), Ok(other)) => path == other,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{env, fs};
+    use std::path::PathBuf;
+    use eyre::Result;
+    use similar_asserts::assert_eq;
+    use crate::cli::{Dialect, WrapSpec};
+    use crate::opts::grammar::Gnu;
+    use crate::util::testing::assert_err_contains;
+
+    fn temp_dir(name: &str) -> Result<PathBuf> {
+        let dir = env::temp_dir().join(format!("oco-wrap-test-{}-{name}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    #[test]
+    fn test_resolve_target_skips_wrapper() -> Result<()> {
+        let dir = temp_dir("resolve")?;
+        let (wrapper_dir, target_dir) = (dir.join("wrapper"), dir.join("target"));
+        fs::create_dir_all(&wrapper_dir)?;
+        fs::create_dir_all(&target_dir)?;
+        fs::write(wrapper_dir.join("prog"), "")?;
+        fs::write(target_dir.join("prog"), "")?;
+
+        let path_var = env::join_paths([&wrapper_dir, &target_dir])?;
+        let wrapper = wrapper_dir.join("prog");
+
+        assert_eq!(super::resolve_target("prog", &path_var, Some(&wrapper))?, target_dir.join("prog"));
+        assert_eq!(super::resolve_target("prog", &path_var, None)?, wrapper_dir.join("prog"));
+        assert_eq!(super::resolve_target(target_dir.join("prog").to_str().unwrap(), &path_var, Some(&wrapper))?,
+                   target_dir.join("prog"));
+        assert_err_contains!(super::resolve_target(wrapper.to_str().unwrap(), &path_var, Some(&wrapper)),
+                             "wrapper script itself");
+        assert_err_contains!(super::resolve_target("other", &path_var, None), "not found");
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_write_wrapper() -> Result<()> {
+        let dir = temp_dir("write")?;
+        let script = dir.join("rules.oco");
+        fs::write(&script, "set color=auto\n")?;
+
+        let spec = WrapSpec {
+            target: "/usr/bin/ls".to_string(),
+            script: script.clone(),
+            full_script_syntax: true,
+            oco: "/opt/oco".to_string(),
+        };
+        let dialect = Dialect::Gnu(Gnu::default());
+        let mut out = Vec::new();
+        super::write_wrapper(&spec, &dialect, &dialect, None, &mut out)?;
+
+        let dialect_arg = format!("'{dialect}'");
+        assert_eq!(String::from_utf8(out)?, format!("\
+#!/bin/sh
+# Generated by oco: processes the arguments with oco, and then runs /usr/bin/ls
+target='/usr/bin/ls'
+args=$(printf '%s\\0' \"$target\" \"$@\" | '/opt/oco' -I {dialect_arg} -O {dialect_arg} \\
+        --from-cmdline-file - -l -c 'set color=auto
+') || exit
+eval \"exec $args\"
+"));
+
+        // Scripts are validated before generating the wrapper
+        fs::write(&script, "bad command\n")?;
+        let mut out = Vec::new();
+        assert_err_contains!(super::write_wrapper(&spec, &dialect, &dialect, None, &mut out), "Failed to parse");
+
+        fs::remove_dir_all(dir)?;
+        Ok(())
+    }
+}